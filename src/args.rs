@@ -14,7 +14,10 @@ find
 infer source (for faster find)
 */
 
-use std::path::PathBuf;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 use structopt::{clap, clap::arg_enum, StructOpt};
 
 #[derive(Debug, StructOpt)]
@@ -28,15 +31,53 @@ pub struct Opt {
 
 #[derive(Debug, StructOpt)]
 pub enum SubCommands {
-    #[structopt(name = "register", about = "register gff")]
+    #[structopt(
+        name = "register",
+        about = "register gff",
+        long_about = "Register an InterProScan result.\n\
+                      Domain and gene rows are written in chunks, so memory doesn't grow with the number of domain rows. \
+                      The gene IDs, the GFF3 polypeptide rows and the catalog of domain names are kept until the input is read, \
+                      so memory still grows with the number of genes and distinct domain names."
+    )]
     #[structopt(setting(clap::AppSettings::ColoredHelp))]
     Register {
-        #[structopt(short = "-i", long = "input", about = "input GFF3 (can gzipped)")]
+        #[structopt(
+            short = "-i",
+            long = "input",
+            about = "input InterProScan result (can gzipped)"
+        )]
         input: PathBuf,
+        #[structopt(
+            long = "input-format",
+            about = "input format (inferred from the extension if omitted)"
+        )]
+        input_format: Option<InputFormat>,
+        #[structopt(
+            long = "fasta",
+            about = "protein FASTA (can gzipped), required for TSV or GFF3 without ##FASTA, not allowed for JSON/XML"
+        )]
+        fasta: Option<PathBuf>,
         #[structopt(short = "-o", long = "org", about = "Organism name")]
         org: String,
+        #[structopt(long = "taxon-id", about = "NCBI taxonomy ID of the organism")]
+        taxon_id: Option<i64>,
+        #[structopt(long = "strain", about = "strain of the organism")]
+        strain: Option<String>,
+        #[structopt(long = "assembly", about = "assembly version of the protein set")]
+        assembly: Option<String>,
         #[structopt(short = "-d", long = "dir", about = "output dir")]
         dir: PathBuf,
+        #[structopt(
+            long = "on-error",
+            default_value = "fail",
+            about = "what to do with malformed GFF3/TSV lines: fail, skip or quarantine (JSON/XML input always fails)"
+        )]
+        on_error: OnError,
+        #[structopt(
+            long = "quarantine-file",
+            about = "where quarantined lines are written (default: <dir>/quarantine/<org>.tsv)"
+        )]
+        quarantine_file: Option<PathBuf>,
     },
     #[structopt(name = "find", about = "find gene(s) which has the specific domain")]
     #[structopt(setting(clap::AppSettings::ColoredHelp))]
@@ -49,6 +90,16 @@ pub enum SubCommands {
         org: Option<Vec<String>>,
         #[structopt(short = "-f", long = "format", about = "output format")]
         format: Option<OutFormat>,
+        #[structopt(
+            long = "max-evalue",
+            about = "ignore domains with e-value above this (domains without e-value are kept)"
+        )]
+        max_evalue: Option<f64>,
+        #[structopt(
+            long = "motif-positions",
+            about = "report the positions of each sequence motif (seq~) of each gene as <motif>:<start>-<end>,..."
+        )]
+        motif_positions: bool,
     },
 }
 
@@ -59,3 +110,41 @@ arg_enum! {
         Fasta,
     }
 }
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum OnError {
+        Fail,
+        Skip,
+        Quarantine,
+    }
+}
+
+arg_enum! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum InputFormat {
+        Gff3,
+        Tsv,
+        Json,
+        Xml,
+    }
+}
+
+impl InputFormat {
+    pub fn infer<P: AsRef<Path>>(p: &P) -> Option<Self> {
+        let p = p.as_ref();
+        let p = if p.extension() == Some(OsStr::new("gz")) {
+            Path::new(p.file_stem()?)
+        } else {
+            p
+        };
+
+        match p.extension()?.to_str()?.to_lowercase().as_str() {
+            "gff" | "gff3" => Some(Self::Gff3),
+            "tsv" => Some(Self::Tsv),
+            "json" => Some(Self::Json),
+            "xml" => Some(Self::Xml),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    num::{ParseFloatError, ParseIntError},
+    path::PathBuf,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::args::OnError;
+
+/// Errors of a single record, distinguished so lenient registration can count them by kind
+#[derive(Debug)]
+pub enum RecordError {
+    /// the line has too few or too many tab-separated columns
+    InvalidLine {
+        columns: usize,
+    },
+    MissingDomainName,
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidLine { columns } => write!(f, "Invalid line with {} columns", columns),
+            Self::MissingDomainName => write!(f, "domain name is required"),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+fn error_kind(err: &anyhow::Error) -> &'static str {
+    if let Some(e) = err.downcast_ref::<RecordError>() {
+        match e {
+            RecordError::InvalidLine { .. } => "invalid line",
+            RecordError::MissingDomainName => "missing domain name",
+        }
+    } else if err.is::<ParseIntError>() || err.is::<ParseFloatError>() {
+        "invalid number"
+    } else {
+        "other"
+    }
+}
+
+/// Decide what happens to a record which can't be parsed (see `--on-error`)
+pub struct ErrorHandler {
+    on_error: OnError,
+    quarantine_path: Option<PathBuf>,
+    quarantine: Option<BufWriter<File>>,
+    counts: BTreeMap<&'static str, usize>,
+}
+
+impl Default for ErrorHandler {
+    fn default() -> Self {
+        Self {
+            on_error: OnError::Fail,
+            quarantine_path: None,
+            quarantine: None,
+            counts: BTreeMap::new(),
+        }
+    }
+}
+
+impl ErrorHandler {
+    pub fn new(on_error: OnError, quarantine_path: PathBuf) -> Self {
+        Self {
+            on_error,
+            quarantine_path: Some(quarantine_path),
+            ..Default::default()
+        }
+    }
+
+    /// `line_no` is 1-based
+    pub fn handle(&mut self, line_no: usize, line: &str, err: anyhow::Error) -> Result<()> {
+        if self.on_error == OnError::Fail {
+            return Err(anyhow!(format!("line {}: {}", line_no, err)));
+        }
+
+        *self.counts.entry(error_kind(&err)).or_insert(0) += 1;
+
+        if self.on_error == OnError::Quarantine {
+            if self.quarantine.is_none() {
+                let path = self
+                    .quarantine_path
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("quarantine file is not set"))?;
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                let mut writer = BufWriter::new(File::create(path)?);
+                writeln!(writer, "line\treason\trecord")?;
+                self.quarantine = Some(writer);
+            }
+
+            if let Some(writer) = self.quarantine.as_mut() {
+                // the reason must not break the columns of the TSV
+                let reason = err.to_string().replace(['\t', '\n'], " ");
+                writeln!(writer, "{}\t{}\t{}", line_no, reason, line.trim_end())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print the number of skipped records by kind
+    pub fn finish(self) -> Result<()> {
+        if let Some(mut writer) = self.quarantine {
+            writer.flush()?;
+        }
+
+        if self.counts.is_empty() {
+            return Ok(());
+        }
+
+        let total: usize = self.counts.values().sum();
+        eprintln!("[WARN] {} record(s) were skipped", total);
+        for (kind, count) in self.counts.iter() {
+            eprintln!("  {}: {}", kind, count);
+        }
+
+        if let (OnError::Quarantine, Some(path)) = (self.on_error, &self.quarantine_path) {
+            eprintln!("[WARN] skipped records are written to {}", path.display());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_errors {
+    use super::*;
+
+    fn invalid_line() -> anyhow::Error {
+        RecordError::InvalidLine { columns: 3 }.into()
+    }
+
+    fn invalid_number() -> anyhow::Error {
+        "x".parse::<i32>().unwrap_err().into()
+    }
+
+    #[test]
+    fn test_fail() {
+        let mut handler = ErrorHandler::default();
+        let err = handler.handle(3, "a\tb\tc", invalid_line()).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: Invalid line with 3 columns");
+    }
+
+    #[test]
+    fn test_skip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quarantine.tsv");
+        let mut handler = ErrorHandler::new(OnError::Skip, path.clone());
+
+        handler.handle(1, "a\tb\tc", invalid_line()).unwrap();
+        handler.handle(2, "a\tb", invalid_line()).unwrap();
+        handler.handle(5, "x", invalid_number()).unwrap();
+        handler
+            .handle(6, "y", RecordError::MissingDomainName.into())
+            .unwrap();
+
+        assert_eq!(
+            handler
+                .counts
+                .iter()
+                .map(|(k, v)| (*k, *v))
+                .collect::<Vec<_>>(),
+            vec![
+                ("invalid line", 2),
+                ("invalid number", 1),
+                ("missing domain name", 1)
+            ]
+        );
+        handler.finish().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_quarantine() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quarantine").join("org.tsv");
+        let mut handler = ErrorHandler::new(OnError::Quarantine, path.clone());
+
+        handler.handle(1, "a\tb\tc\n", invalid_line()).unwrap();
+        handler.handle(4, "x", invalid_number()).unwrap();
+        handler
+            .handle(7, "y", anyhow!("a reason\twith a tab"))
+            .unwrap();
+        handler.finish().unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let rows: Vec<Vec<&str>> = content
+            .lines()
+            .map(|line| line.splitn(3, '\t').collect())
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["line", "reason", "record"],
+                vec!["1", "Invalid line with 3 columns", "a\tb\tc"],
+                vec!["4", "invalid digit found in string", "x"],
+                vec!["7", "a reason with a tab", "y"],
+            ]
+        );
+    }
+}
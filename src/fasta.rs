@@ -0,0 +1,110 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufRead, Lines},
+    iter::Peekable,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::gff3::read_with_gz;
+
+pub struct FastaRecord {
+    pub gene_id: String,
+    pub desc: Option<String>,
+    pub seq: String,
+}
+
+/// Parse FASTA records one by one from lines, so whole sequences sets are never held in memory
+pub struct FastaRecords<L: Iterator<Item = io::Result<String>>> {
+    lines: Peekable<L>,
+}
+
+impl<L: Iterator<Item = io::Result<String>>> FastaRecords<L> {
+    pub fn new(lines: L) -> Self {
+        Self {
+            lines: lines.peekable(),
+        }
+    }
+}
+
+impl<L: Iterator<Item = io::Result<String>>> Iterator for FastaRecords<L> {
+    type Item = Result<FastaRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => break line,
+                Err(e) => return Some(Err(e.into())),
+            }
+        };
+
+        if !header.starts_with('>') {
+            return Some(Err(anyhow!("Expected > at record start")));
+        }
+
+        let mut header_fields = header[1..].trim_end().splitn(2, char::is_whitespace);
+        let gene_id = header_fields.next().map(|s| s.to_owned()).unwrap();
+        let desc = header_fields.next().map(|s| s.to_owned());
+
+        let mut seq = String::new();
+        loop {
+            match self.lines.peek() {
+                Some(Ok(line)) if !line.starts_with('>') => seq.push_str(line.trim()),
+                _ => break,
+            }
+            self.lines.next();
+        }
+
+        Some(Ok(FastaRecord { gene_id, desc, seq }))
+    }
+}
+
+pub fn read_fasta_records<P: AsRef<Path>>(p: &P) -> Result<FastaRecords<Lines<Box<dyn BufRead>>>> {
+    Ok(FastaRecords::new(read_with_gz(p)?.lines()))
+}
+
+/// The first IDs of `ids` for a warning
+pub fn preview(ids: &[&str]) -> String {
+    const MAX_PREVIEW: usize = 10;
+
+    if ids.len() > MAX_PREVIEW {
+        format!("{}, ...", ids[..MAX_PREVIEW].join(", "))
+    } else {
+        ids.join(", ")
+    }
+}
+
+/// Report gene IDs which appear only in the domain rows or only in the FASTA
+pub fn report_unmatched_ids(domain_gene_ids: &HashSet<String>, fasta_gene_ids: &HashSet<String>) {
+    let mut only_domain: Vec<&str> = domain_gene_ids
+        .iter()
+        .map(|id| id.as_str())
+        .filter(|id| !fasta_gene_ids.contains(*id))
+        .collect();
+    only_domain.sort_unstable();
+
+    let mut only_fasta: Vec<&str> = fasta_gene_ids
+        .iter()
+        .map(|id| id.as_str())
+        .filter(|id| !domain_gene_ids.contains(*id))
+        .collect();
+    only_fasta.sort_unstable();
+
+    if !only_domain.is_empty() {
+        eprintln!(
+            "[WARN] {} gene(s) have domains but no sequence: {}",
+            only_domain.len(),
+            preview(&only_domain)
+        );
+    }
+
+    if !only_fasta.is_empty() {
+        eprintln!(
+            "[WARN] {} gene(s) have a sequence but no domains: {}",
+            only_fasta.len(),
+            preview(&only_fasta)
+        );
+    }
+}
@@ -1,15 +1,20 @@
 use std::{
+    collections::{HashMap, HashSet},
     ffi::OsStr,
     fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
 use anyhow::{anyhow, Result};
 use flate2::read::MultiGzDecoder;
 
-use crate::records::{DomainRecords, GeneRecords, Term};
+use crate::{
+    errors::{ErrorHandler, RecordError},
+    fasta::{preview, read_fasta_records, report_unmatched_ids, FastaRecords},
+    records::{seq_md5, Domain, DomainRecords, GeneRecords, Organism, Term},
+};
 
 fn is_compressed<P: AsRef<Path>>(p: &P) -> bool {
     let ext = p.as_ref().extension();
@@ -29,12 +34,98 @@ pub fn read_with_gz<P: AsRef<Path>>(p: &P) -> Result<Box<dyn BufRead>> {
     Ok(reader)
 }
 
-pub fn parse_gffrecord_line(line: &str, domain_records: &mut DomainRecords) -> Result<()> {
+/// The `polypeptide` row of a protein, declaring its length and the md5 of its sequence
+/// e.g. `119473 . polypeptide 1 281 . + . ID=119473;md5=83d397631d630c933e051fec0b00e7e7`
+pub struct Polypeptide {
+    pub gene_id: String,
+    pub length: i32,
+    pub md5: Option<String>,
+}
+
+impl Polypeptide {
+    fn parse(records: &[&str]) -> Result<Self> {
+        let length: i32 = records[4].parse()?;
+        let md5 = records[8]
+            .split(';')
+            .find_map(|attr| attr.strip_prefix("md5="))
+            .map(|s| s.to_string());
+
+        Ok(Self {
+            gene_id: records[0].to_string(),
+            length,
+            md5,
+        })
+    }
+
+    /// Check that the sequence in ##FASTA is the one InterProScan was run on
+    pub fn check(&self, seq: &str) -> Result<()> {
+        if seq.len() != self.length as usize {
+            return Err(anyhow!(format!(
+                "{}: length in GFF3 ({}) does not match the sequence ({})",
+                self.gene_id,
+                self.length,
+                seq.len()
+            )));
+        }
+
+        if let Some(md5) = &self.md5 {
+            let seq_md5 = seq_md5(seq);
+            if !md5.eq_ignore_ascii_case(&seq_md5) {
+                return Err(anyhow!(format!(
+                    "{}: md5 in GFF3 ({}) does not match the sequence ({})",
+                    self.gene_id, md5, seq_md5
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decode the `%XX` escapes of a GFF3 column (e.g. `%3B` for `;`), keeping invalid ones as they are
+fn percent_decode(s: &str) -> String {
+    if !s.contains('%') {
+        return s.to_string();
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Parse a feature line into `domain_records`.
+/// `polypeptide` rows don't have domains, so they are returned instead.
+pub fn parse_gffrecord_line(
+    line: &str,
+    domain_records: &mut DomainRecords,
+) -> Result<Option<Polypeptide>> {
     let line = line.trim();
 
     let records: Vec<&str> = line.split('\t').collect();
     if records.len() != 9 {
-        return Err(anyhow!(format!("Invalid line: {}", line)));
+        return Err(RecordError::InvalidLine {
+            columns: records.len(),
+        }
+        .into());
     }
 
     let id = records[0];
@@ -42,150 +133,155 @@ pub fn parse_gffrecord_line(line: &str, domain_records: &mut DomainRecords) -> R
     let source = Term::from_str(records[1])?;
 
     if source == Term::ID {
-        return Ok(());
+        return Ok(Some(Polypeptide::parse(&records)?));
     }
 
-    let start: i16 = records[3].parse()?;
-    let end: i16 = records[4].parse()?;
+    let start: i32 = records[3].parse()?;
+    let end: i32 = records[4].parse()?;
+    let evalue: Option<f64> = match records[5] {
+        "." => None,
+        v => Some(v.parse()?),
+    };
+
+    let strand = match records[6] {
+        "." => None,
+        v => Some(v.to_string()),
+    };
+
+    let mut base = Domain {
+        evalue,
+        strand,
+        ..Domain::new(start, end, String::new(), None, id.to_string())
+    };
 
     let mut domain_name = None;
     let mut domain_desc = None;
+    let mut ontology_terms = None;
+    let mut dbxrefs = None;
+    let mut attributes = Vec::new();
     for attr in records[8].split(';') {
-        let attr_records: Vec<&str> = attr.split('=').collect();
-
-        if attr_records.len() != 2 {
-            continue;
-        }
-
-        let (key, values) = (attr_records[0], attr_records[1]);
-
-        if key == "Name" {
-            domain_name = Some(values);
-        } else if key == "signature_desc" {
-            domain_desc = Some(values)
-        } else if key == "Ontology_term" {
-            // Ontology_term="GO:0008654","GO:0016020","GO:0016780"
-            for term_name in values.split(',') {
-                let term_name = term_name.trim_matches('\"');
-                domain_records.push(
-                    Term::GoTerm,
-                    start,
-                    end,
-                    term_name.to_string(),
-                    None,
-                    id.to_string(),
-                )?;
-            }
-        } else if attr_records[0] == "Dbxref" {
-            for type_term in values.split(',') {
-                let type_term: Vec<&str> = type_term.trim_matches('\"').split(':').collect();
-                let (type_, term_name) = (type_term[0], type_term[1]);
-
-                match type_ {
-                    "InterPro" => domain_records.push(
-                        Term::InterPro,
-                        start,
-                        end,
-                        term_name.to_string(),
-                        None,
-                        id.to_string(),
-                    )?,
-                    "MetaCyc" => domain_records.push(
-                        Term::MetaCyc,
-                        start,
-                        end,
-                        term_name.to_string(),
-                        None,
-                        id.to_string(),
-                    )?,
-
-                    "Reactome" => domain_records.push(
-                        Term::Reactome,
-                        start,
-                        end,
-                        term_name.to_string(),
-                        None,
-                        id.to_string(),
-                    )?,
-                    _ => unreachable!(),
+        // only the first `=` separates the key, values may contain more
+        let (key, values) = match attr.split_once('=') {
+            Some(key_values) => key_values,
+            None => continue,
+        };
+
+        match key {
+            "Name" => domain_name = Some(percent_decode(values)),
+            "signature_desc" => domain_desc = Some(percent_decode(values)),
+            // multiple values are split on `,` before decoding, since escaped commas belong to a value
+            "Ontology_term" => ontology_terms = Some(values),
+            "Dbxref" => dbxrefs = Some(values),
+            "status" => base.status = Some(percent_decode(values)),
+            "date" => base.date = Some(percent_decode(values)),
+            "ID" => base.match_id = Some(percent_decode(values)),
+            "Target" => {
+                // Target=119473 59 243
+                let values = percent_decode(values);
+                let target: Vec<&str> = values.split_whitespace().collect();
+                if target.len() == 3 {
+                    base.target_start = Some(target[1].parse()?);
+                    base.target_end = Some(target[2].parse()?);
                 }
             }
+            _ => attributes.push((percent_decode(key), percent_decode(values))),
         }
     }
 
-    if let Some(domain_name) = domain_name {
-        domain_records.push(
-            source,
-            start,
-            end,
-            domain_name.to_string(),
-            domain_desc.map(|s| s.to_string()),
-            id.to_string(),
-        )?;
-
-        Ok(())
-    } else {
-        Err(anyhow!("domain name is required"))
-    }
-}
-
-fn parse_fasta_lines(fasta_lines: &[String], organism: String) -> Result<GeneRecords> {
-    let mut gene_records = GeneRecords::new(5000);
-
-    let mut iter = fasta_lines.iter().peekable();
+    let domain_name = domain_name.ok_or(RecordError::MissingDomainName)?;
 
-    fn is_header(line: &str) -> bool {
-        line.starts_with('>')
-    }
+    // cross references share the location and the match attributes
+    let row = |domain_name: &str, domain_desc: Option<&str>| Domain {
+        domain_name: domain_name.to_string(),
+        domain_desc: domain_desc.map(|s| s.to_string()),
+        ..base.clone()
+    };
 
-    while let Some(line) = iter.next() {
-        if !is_header(line) {
-            return Err(anyhow!("Expected > at record start"));
+    if let Some(values) = ontology_terms {
+        // Ontology_term="GO:0008654","GO:0016020","GO:0016780"
+        for term_name in values.split(',') {
+            let term_name = percent_decode(term_name.trim_matches('\"'));
+            domain_records.push(Term::GoTerm, row(&term_name, None))?;
         }
+    }
 
-        let mut header_fields = line[1..].trim_end().splitn(2, char::is_whitespace);
-        let gene_id = header_fields.next().map(|s| s.to_owned()).unwrap();
-        let desc = header_fields.next().map(|s| s.to_owned());
-
-        let mut seq = String::new();
-        while let Some(peek_line) = iter.peek() {
-            if is_header(peek_line) {
-                break;
-            }
+    if let Some(values) = dbxrefs {
+        // Dbxref="InterPro:IPR041492","MetaCyc:PWY-181","Reactome:R-HSA-73614"
+        for type_term in values.split(',') {
+            let type_term = percent_decode(type_term.trim_matches('\"'));
+            let (term, term_name) = match type_term.split_once(':') {
+                Some((type_, term_name)) => Term::from_xref(type_, term_name),
+                None => (Term::Xref, type_term.clone()),
+            };
 
-            seq.push_str(iter.next().unwrap())
+            domain_records.push(term, row(&term_name, None))?;
         }
-
-        gene_records.push(gene_id, seq, desc, organism.clone())?;
     }
 
-    Ok(gene_records)
+    domain_records.push(
+        source,
+        Domain {
+            attributes,
+            ..row(&domain_name, domain_desc.as_deref())
+        },
+    )?;
+
+    Ok(None)
 }
 
 pub struct Reader {
     reader: Box<dyn BufRead>,
+    fasta: Option<PathBuf>,
+    organism: Organism,
+    errors: ErrorHandler,
 }
 
 impl Reader {
     pub fn from_path<P: AsRef<Path>>(p: &P) -> Result<Self> {
         Ok(Self {
             reader: read_with_gz(p)?,
+            fasta: None,
+            organism: Organism::default(),
+            errors: ErrorHandler::default(),
         })
     }
 
-    pub fn finish(self) -> Result<(DomainRecords, GeneRecords)> {
+    /// Read sequences from a separate FASTA instead of the ##FASTA section
+    pub fn with_fasta(mut self, fasta: Option<PathBuf>) -> Self {
+        self.fasta = fasta;
+        self
+    }
+
+    pub fn with_organism(mut self, organism: Organism) -> Self {
+        self.organism = organism;
+        self
+    }
+
+    pub fn with_error_handler(mut self, errors: ErrorHandler) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Stream the records into `domain_records` and `gene_records`.
+    /// The polypeptide rows and gene IDs are kept in memory until the FASTA is read,
+    /// so memory grows with the number of genes, though not with the number of domain rows.
+    pub fn finish(
+        mut self,
+        domain_records: &mut DomainRecords,
+        gene_records: &mut GeneRecords,
+    ) -> Result<()> {
         let comment = '#';
         let fasta_line = "##FASTA";
 
-        let mut domain_records = DomainRecords::new(10000);
-
-        let mut is_fasta = false;
-        let mut fasta_lines = Vec::new();
-        for line in self.reader.lines() {
+        let mut has_fasta_section = false;
+        let mut polypeptides = HashMap::new();
+        let mut gene_ids = HashSet::new();
+        let mut lines = self.reader.lines();
+        for (i, line) in lines.by_ref().enumerate() {
             let line = line?;
             if line.starts_with(fasta_line) {
-                is_fasta = true;
-                continue;
+                has_fasta_section = true;
+                break;
             }
 
             if line.starts_with(comment) {
@@ -196,15 +292,289 @@ impl Reader {
                 continue;
             }
 
-            if !is_fasta {
-                parse_gffrecord_line(&line, &mut domain_records)?;
-            } else {
-                fasta_lines.push(line);
+            match parse_gffrecord_line(&line, domain_records) {
+                Ok(Some(polypeptide)) => {
+                    polypeptides.insert(polypeptide.gene_id.clone(), polypeptide);
+                }
+                // like the TSV reader, only the domain rows which parsed count as genes with domains
+                Ok(None) => {
+                    if let Some(gene_id) = line.split('\t').next() {
+                        if !gene_ids.contains(gene_id) {
+                            gene_ids.insert(gene_id.to_string());
+                        }
+                    }
+                }
+                Err(e) => self.errors.handle(i + 1, &line, e)?,
+            }
+        }
+
+        self.errors.finish()?;
+
+        let fasta_gene_ids = match &self.fasta {
+            Some(fasta) => {
+                if has_fasta_section {
+                    eprintln!("[WARN] ##FASTA section is ignored, sequences are read from --fasta");
+                }
+                push_fasta_records(
+                    read_fasta_records(fasta)?,
+                    &mut polypeptides,
+                    &self.organism,
+                    gene_records,
+                )?
+            }
+            None => push_fasta_records(
+                FastaRecords::new(lines),
+                &mut polypeptides,
+                &self.organism,
+                gene_records,
+            )?,
+        };
+
+        push_unsequenced_polypeptides(polypeptides, &self.organism, gene_records)?;
+
+        if fasta_gene_ids.is_empty() {
+            match &self.fasta {
+                Some(fasta) => eprintln!("[WARN] No sequences found in {}", fasta.display()),
+                None if has_fasta_section => {
+                    eprintln!(
+                        "[WARN] No sequences found, the ##FASTA section is empty (use --fasta)"
+                    )
+                }
+                None => {
+                    eprintln!(
+                        "[WARN] No sequences found, the GFF3 has no ##FASTA section (use --fasta)"
+                    )
+                }
+            }
+        }
+
+        report_unmatched_ids(&gene_ids, &fasta_gene_ids);
+
+        Ok(())
+    }
+}
+
+/// Push FASTA records checked against the polypeptide rows, and return their gene IDs.
+/// The polypeptide rows of the pushed records are removed from `polypeptides`.
+fn push_fasta_records<L: Iterator<Item = io::Result<String>>>(
+    fasta_records: FastaRecords<L>,
+    polypeptides: &mut HashMap<String, Polypeptide>,
+    organism: &Organism,
+    gene_records: &mut GeneRecords,
+) -> Result<HashSet<String>> {
+    let mut gene_ids = HashSet::new();
+
+    for record in fasta_records {
+        let record = record?;
+        gene_ids.insert(record.gene_id.clone());
+
+        match polypeptides.remove(&record.gene_id) {
+            Some(polypeptide) => {
+                polypeptide.check(&record.seq)?;
+                gene_records.push_declared(
+                    record.gene_id,
+                    Some(record.seq),
+                    record.desc,
+                    polypeptide.length,
+                    polypeptide.md5,
+                    organism,
+                )?;
             }
+            None => gene_records.push(record.gene_id, record.seq, record.desc, organism)?,
         }
+    }
+
+    Ok(gene_ids)
+}
+
+/// Push the polypeptides which never got a sequence with their declared length and md5
+fn push_unsequenced_polypeptides(
+    polypeptides: HashMap<String, Polypeptide>,
+    organism: &Organism,
+    gene_records: &mut GeneRecords,
+) -> Result<()> {
+    let mut polypeptides: Vec<Polypeptide> = polypeptides.into_values().collect();
+    if polypeptides.is_empty() {
+        return Ok(());
+    }
+    polypeptides.sort_unstable_by(|a, b| a.gene_id.cmp(&b.gene_id));
+
+    let gene_ids: Vec<&str> = polypeptides.iter().map(|p| p.gene_id.as_str()).collect();
+    eprintln!(
+        "[WARN] {} polypeptide(s) have no sequence, keeping their declared length and md5: {}",
+        gene_ids.len(),
+        preview(&gene_ids)
+    );
+
+    for polypeptide in polypeptides {
+        gene_records.push_declared(
+            polypeptide.gene_id,
+            None,
+            None,
+            polypeptide.length,
+            polypeptide.md5,
+            organism,
+        )?;
+    }
+
+    Ok(())
+}
 
-        let gene_records = parse_fasta_lines(&fasta_lines, "Olucimarinus".to_string())?;
+#[cfg(test)]
+mod test_gff3 {
+    use polars::prelude::DataFrame;
+
+    use super::*;
+    use crate::records::test_utils::{domain_rows, register, row_of, utf8_at};
+
+    fn register_gff3(gff3: &str) -> Result<(DataFrame, DataFrame)> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("output.gff3");
+        std::fs::write(&path, gff3)?;
+
+        register(|domain_records, gene_records| {
+            Reader::from_path(&path)?.finish(domain_records, gene_records)
+        })
+    }
+
+    /// The `attribute_keys` or `attribute_values` list of `row`
+    fn attribute_list(df: &DataFrame, column: &str, row: usize) -> Option<Vec<String>> {
+        let list = df[column].list().unwrap().get(row)?;
+        let values = list
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.unwrap_or_default().to_string())
+            .collect();
+        Some(values)
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("no escapes"), "no escapes");
+        assert_eq!(percent_decode("a%3Bb%3dc%2Cd%25"), "a;b=c,d%");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        // invalid escapes are kept
+        assert_eq!(percent_decode("50% %zz %4"), "50% %zz %4");
+    }
+
+    #[test]
+    fn test_attributes() {
+        let line = "g1\tPfam\tprotein_match\t2\t8\t1.5E-20\t+\t.\t\
+                    Name=PF00069;signature_desc=Kinase%3B catalytic;ID=match$1_2_8;status=T;\
+                    date=21-04-2022;Target=g1 2 8;note=a=b;comment=x%3Dy%2Cz;\
+                    Dbxref=\"InterPro:IPR000719\",\"Reactome:R-HSA-1%2C2\"";
+        let (df, _) =
+            register(|domain_records, _| parse_gffrecord_line(line, domain_records).map(|_| ()))
+                .unwrap();
+
+        assert_eq!(
+            domain_rows(&df),
+            vec![
+                "g1 InterPro IPR000719 2 8",
+                "g1 Pfam PF00069 2 8",
+                "g1 Reactome R-HSA-1,2 2 8"
+            ]
+        );
+
+        let i = row_of(&df, "domain_name", "PF00069");
+        assert_eq!(
+            utf8_at(&df, "domain_desc", i).as_deref(),
+            Some("Kinase; catalytic")
+        );
+        assert_eq!(utf8_at(&df, "status", i).as_deref(), Some("T"));
+        assert_eq!(utf8_at(&df, "date", i).as_deref(), Some("21-04-2022"));
+        assert_eq!(utf8_at(&df, "match_id", i).as_deref(), Some("match$1_2_8"));
+        assert_eq!(df["target_start"].i32().unwrap().get(i), Some(2));
+        assert_eq!(df["target_end"].i32().unwrap().get(i), Some(8));
+        assert_eq!(utf8_at(&df, "strand", i).as_deref(), Some("+"));
+        assert_eq!(
+            attribute_list(&df, "attribute_keys", i),
+            Some(vec!["note".to_string(), "comment".to_string()])
+        );
+        assert_eq!(
+            attribute_list(&df, "attribute_values", i),
+            Some(vec!["a=b".to_string(), "x=y,z".to_string()])
+        );
+
+        // cross references share the match attributes, but not the extra ones
+        let i = row_of(&df, "domain_name", "IPR000719");
+        assert_eq!(utf8_at(&df, "match_id", i).as_deref(), Some("match$1_2_8"));
+        assert_eq!(attribute_list(&df, "attribute_keys", i), None);
+    }
+
+    #[test]
+    fn test_polypeptides() {
+        let gff3 = format!(
+            "##gff-version 3\n\
+             g1\t.\tpolypeptide\t1\t9\t.\t+\t.\tID=g1;md5={}\n\
+             g1\tPfam\tprotein_match\t2\t8\t1.5E-20\t+\t.\tName=PF00069;ID=match$1_2_8\n\
+             g2\t.\tpolypeptide\t1\t120\t.\t+\t.\tID=g2;md5=0123456789abcdef0123456789abcdef\n\
+             g2\tPfam\tprotein_match\t10\t80\t1.0E-5\t+\t.\tName=PF00069;ID=match$2_10_80\n\
+             g3\tSMART\tprotein_match\t1\t3\t.\t+\t.\tName=SM00220\n\
+             ##FASTA\n\
+             >g1 kinase\n\
+             MKVLA\n\
+             AGIV\n\
+             >g3\n\
+             MAL\n",
+            seq_md5("MKVLAAGIV")
+        );
+        let (domain_df, gene_df) = register_gff3(&gff3).unwrap();
+
+        assert_eq!(
+            domain_rows(&domain_df),
+            vec![
+                "g1 Pfam PF00069 2 8",
+                "g2 Pfam PF00069 10 80",
+                "g3 SMART SM00220 1 3"
+            ]
+        );
+
+        let lengths = gene_df["length"].i32().unwrap();
+        let declared_lengths = gene_df["declared_length"].i32().unwrap();
+        assert_eq!(gene_df.height(), 3);
+
+        let i = row_of(&gene_df, "gene_id", "g1");
+        assert_eq!(utf8_at(&gene_df, "seq", i).as_deref(), Some("MKVLAAGIV"));
+        assert_eq!(utf8_at(&gene_df, "desc", i).as_deref(), Some("kinase"));
+        assert_eq!(declared_lengths.get(i), Some(9));
+        assert_eq!(utf8_at(&gene_df, "md5", i), Some(seq_md5("MKVLAAGIV")));
+
+        // a polypeptide without a sequence keeps its declared values
+        let i = row_of(&gene_df, "gene_id", "g2");
+        assert_eq!(utf8_at(&gene_df, "seq", i), None);
+        assert_eq!(lengths.get(i), Some(120));
+        assert_eq!(declared_lengths.get(i), Some(120));
+        assert_eq!(
+            utf8_at(&gene_df, "md5", i).as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+
+        // a sequence without a polypeptide row
+        let i = row_of(&gene_df, "gene_id", "g3");
+        assert_eq!(lengths.get(i), Some(3));
+        assert_eq!(declared_lengths.get(i), None);
+        assert_eq!(utf8_at(&gene_df, "md5", i), Some(seq_md5("MAL")));
+    }
 
-        Ok((domain_records, gene_records))
+    #[test]
+    fn test_polypeptide_mismatch() {
+        let gff3 = "g1\t.\tpolypeptide\t1\t10\t.\t+\t.\tID=g1\n##FASTA\n>g1\nMKVLAAGIV\n";
+        assert_eq!(
+            register_gff3(gff3).unwrap_err().to_string(),
+            "g1: length in GFF3 (10) does not match the sequence (9)"
+        );
+
+        let gff3 =
+            "g1\t.\tpolypeptide\t1\t9\t.\t+\t.\tID=g1;md5=0123456789abcdef0123456789abcdef\n\
+                    ##FASTA\n>g1\nMKVLAAGIV\n";
+        assert_eq!(
+            register_gff3(gff3).unwrap_err().to_string(),
+            format!(
+                "g1: md5 in GFF3 (0123456789abcdef0123456789abcdef) does not match the sequence ({})",
+                seq_md5("MKVLAAGIV")
+            )
+        );
     }
 }
@@ -0,0 +1,366 @@
+use std::{io::BufRead, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::{
+    gff3::read_with_gz,
+    records::{Domain, DomainRecords, GeneRecords, Organism, Term},
+};
+
+// Only the fields used for registration are declared, the others are ignored by serde.
+// The XML reader builds the same structs, so both formats share `push_match`.
+// https://interproscan-docs.readthedocs.io/en/latest/OutputFormats.html#json-output
+
+#[derive(Debug, Deserialize)]
+struct Output {
+    results: Vec<Protein>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Protein {
+    sequence: String,
+    #[serde(default)]
+    matches: Vec<Match>,
+    #[serde(default)]
+    xref: Vec<ProteinXref>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProteinXref {
+    id: String,
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Match {
+    pub signature: Signature,
+    #[serde(default)]
+    pub locations: Vec<Location>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Signature {
+    pub accession: String,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    #[serde(rename = "signatureLibraryRelease")]
+    pub library_release: LibraryRelease,
+    pub entry: Option<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryRelease {
+    pub library: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Entry {
+    pub accession: String,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub type_: Option<String>,
+    #[serde(rename = "goXRefs", default)]
+    pub go_xrefs: Vec<GoXref>,
+    #[serde(rename = "pathwayXRefs", default)]
+    pub pathway_xrefs: Vec<PathwayXref>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoXref {
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PathwayXref {
+    #[serde(rename = "databaseName")]
+    pub database_name: String,
+    pub id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Location {
+    pub start: i64,
+    pub end: i64,
+    pub evalue: Option<f64>,
+    pub score: Option<f64>,
+    #[serde(rename = "hmmStart")]
+    pub hmm_start: Option<i64>,
+    #[serde(rename = "hmmEnd")]
+    pub hmm_end: Option<i64>,
+    #[serde(rename = "hmmBounds")]
+    pub hmm_bounds: Option<String>,
+    #[serde(rename = "envelopeStart")]
+    pub env_start: Option<i64>,
+    #[serde(rename = "envelopeEnd")]
+    pub env_end: Option<i64>,
+}
+
+fn to_coord(v: i64) -> Result<i32> {
+    i32::try_from(v).map_err(|_| anyhow!(format!("coordinate out of range: {}", v)))
+}
+
+fn to_opt_coord(v: Option<i64>) -> Result<Option<i32>> {
+    v.map(to_coord).transpose()
+}
+
+pub fn push_match(m: &Match, gene_id: &str, domain_records: &mut DomainRecords) -> Result<()> {
+    let signature = &m.signature;
+    let source = Term::from_library(&signature.library_release.library);
+    let domain_desc = signature
+        .description
+        .clone()
+        .or_else(|| signature.name.clone());
+
+    let entry = signature.entry.as_ref();
+    let entry_type = entry.and_then(|e| e.type_.clone());
+    let entry_name = entry.and_then(|e| e.name.clone());
+
+    for location in m.locations.iter() {
+        let start = to_coord(location.start)?;
+        let end = to_coord(location.end)?;
+
+        if let Some(entry) = entry {
+            domain_records.push(
+                Term::InterPro,
+                Domain {
+                    evalue: location.evalue,
+                    entry_type: entry_type.clone(),
+                    entry_name: entry_name.clone(),
+                    ..Domain::new(
+                        start,
+                        end,
+                        entry.accession.clone(),
+                        None,
+                        gene_id.to_string(),
+                    )
+                },
+            )?;
+
+            for go in entry.go_xrefs.iter() {
+                domain_records.push(
+                    Term::GoTerm,
+                    Domain {
+                        evalue: location.evalue,
+                        ..Domain::new(start, end, go.id.clone(), None, gene_id.to_string())
+                    },
+                )?;
+            }
+
+            for pathway in entry.pathway_xrefs.iter() {
+                let (term, term_name) = Term::from_xref(&pathway.database_name, &pathway.id);
+
+                domain_records.push(
+                    term,
+                    Domain {
+                        evalue: location.evalue,
+                        ..Domain::new(start, end, term_name, None, gene_id.to_string())
+                    },
+                )?;
+            }
+        }
+
+        domain_records.push(
+            source.clone(),
+            Domain {
+                evalue: location.evalue,
+                score: location.score,
+                hmm_start: to_opt_coord(location.hmm_start)?,
+                hmm_end: to_opt_coord(location.hmm_end)?,
+                hmm_bounds: location.hmm_bounds.clone(),
+                env_start: to_opt_coord(location.env_start)?,
+                env_end: to_opt_coord(location.env_end)?,
+                entry_type: entry_type.clone(),
+                entry_name: entry_name.clone(),
+                ..Domain::new(
+                    start,
+                    end,
+                    signature.accession.clone(),
+                    domain_desc.clone(),
+                    gene_id.to_string(),
+                )
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+pub struct Reader {
+    reader: Box<dyn BufRead>,
+    organism: Organism,
+}
+
+impl Reader {
+    pub fn from_path<P: AsRef<Path>>(p: &P) -> Result<Self> {
+        Ok(Self {
+            reader: read_with_gz(p)?,
+            organism: Organism::default(),
+        })
+    }
+
+    pub fn with_organism(mut self, organism: Organism) -> Self {
+        self.organism = organism;
+        self
+    }
+
+    pub fn finish(
+        self,
+        domain_records: &mut DomainRecords,
+        gene_records: &mut GeneRecords,
+    ) -> Result<()> {
+        let output: Output = serde_json::from_reader(self.reader)?;
+
+        for protein in output.results.iter() {
+            // identical sequences are merged into a single result with several xrefs
+            for xref in protein.xref.iter() {
+                for m in protein.matches.iter() {
+                    push_match(m, &xref.id, domain_records)?;
+                }
+
+                let desc = xref
+                    .name
+                    .as_deref()
+                    .and_then(|name| name.strip_prefix(xref.id.as_str()))
+                    .map(|desc| desc.trim().to_string())
+                    .filter(|desc| !desc.is_empty());
+
+                gene_records.push(
+                    xref.id.clone(),
+                    protein.sequence.clone(),
+                    desc,
+                    &self.organism,
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_json {
+    use super::*;
+    use crate::records::test_utils::{domain_rows, register, row_of, utf8_at};
+
+    // two identical sequences merged into a single result with two xrefs
+    const JSON: &str = r#"{
+  "interproscan-version": "5.59-91.0",
+  "results": [{
+    "sequence": "MKVLAAGIV",
+    "md5": "0c5d6e4c4fb2d0e6c2a8a1f4a3d6f7e1",
+    "matches": [{
+      "signature": {
+        "accession": "PF00069",
+        "name": "Pkinase",
+        "description": "Protein kinase domain",
+        "signatureLibraryRelease": {"library": "PFAM", "version": "35.0"},
+        "entry": {
+          "accession": "IPR000719",
+          "name": "PROTEIN_KINASE_DOM",
+          "description": "Protein kinase domain",
+          "type": "DOMAIN",
+          "goXRefs": [{"name": "protein kinase activity", "databaseName": "GO", "category": "MOLECULAR_FUNCTION", "id": "GO:0004672"}],
+          "pathwayXRefs": [{"name": "Signaling", "databaseName": "Reactome", "id": "R-HSA-162582"}]
+        }
+      },
+      "locations": [{
+        "start": 2, "end": 8, "evalue": 1.5E-20, "score": 70.1,
+        "hmmStart": 3, "hmmEnd": 250, "hmmLength": 264, "hmmBounds": "INCOMPLETE",
+        "envelopeStart": 1, "envelopeEnd": 9
+      }]
+    }, {
+      "signature": {
+        "accession": "NF000001",
+        "signatureLibraryRelease": {"library": "NCBIFAM", "version": "9.0"},
+        "entry": null
+      },
+      "locations": [{"start": 1, "end": 4}]
+    }],
+    "xref": [{"name": "g1 serine/threonine kinase", "id": "g1"}, {"name": "g2", "id": "g2"}]
+  }]
+}"#;
+
+    #[test]
+    fn test_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.json");
+        std::fs::write(&path, JSON).unwrap();
+
+        let (domain_df, gene_df) = register(|domain_records, gene_records| {
+            Reader::from_path(&path)?.finish(domain_records, gene_records)
+        })
+        .unwrap();
+
+        let mut expected = Vec::new();
+        for gene_id in ["g1", "g2"] {
+            expected.push(format!("{} GoTerm GO:0004672 2 8", gene_id));
+            expected.push(format!("{} InterPro IPR000719 2 8", gene_id));
+            expected.push(format!("{} NCBIfam NF000001 1 4", gene_id));
+            expected.push(format!("{} Pfam PF00069 2 8", gene_id));
+            expected.push(format!("{} Reactome R-HSA-162582 2 8", gene_id));
+        }
+        assert_eq!(domain_rows(&domain_df), expected);
+
+        let i = row_of(&domain_df, "domain_name", "PF00069");
+        assert_eq!(
+            utf8_at(&domain_df, "domain_desc", i).as_deref(),
+            Some("Protein kinase domain")
+        );
+        assert_eq!(domain_df["evalue"].f64().unwrap().get(i), Some(1.5E-20));
+        assert_eq!(domain_df["score"].f64().unwrap().get(i), Some(70.1));
+        assert_eq!(domain_df["hmm_start"].i32().unwrap().get(i), Some(3));
+        assert_eq!(domain_df["hmm_end"].i32().unwrap().get(i), Some(250));
+        assert_eq!(
+            utf8_at(&domain_df, "hmm_bounds", i).as_deref(),
+            Some("INCOMPLETE")
+        );
+        assert_eq!(domain_df["env_start"].i32().unwrap().get(i), Some(1));
+        assert_eq!(domain_df["env_end"].i32().unwrap().get(i), Some(9));
+        assert_eq!(
+            utf8_at(&domain_df, "entry_type", i).as_deref(),
+            Some("DOMAIN")
+        );
+        assert_eq!(
+            utf8_at(&domain_df, "entry_name", i).as_deref(),
+            Some("PROTEIN_KINASE_DOM")
+        );
+
+        // a signature without description falls back to nothing, not to the entry
+        let i = row_of(&domain_df, "domain_name", "NF000001");
+        assert_eq!(utf8_at(&domain_df, "domain_desc", i), None);
+        assert_eq!(utf8_at(&domain_df, "entry_type", i), None);
+
+        assert_eq!(gene_df.height(), 2);
+        let i = row_of(&gene_df, "gene_id", "g1");
+        assert_eq!(
+            utf8_at(&gene_df, "desc", i).as_deref(),
+            Some("serine/threonine kinase")
+        );
+        assert_eq!(utf8_at(&gene_df, "seq", i).as_deref(), Some("MKVLAAGIV"));
+        assert_eq!(gene_df["length"].i32().unwrap().get(i), Some(9));
+        let i = row_of(&gene_df, "gene_id", "g2");
+        assert_eq!(utf8_at(&gene_df, "desc", i), None);
+        assert_eq!(utf8_at(&gene_df, "seq", i).as_deref(), Some("MKVLAAGIV"));
+    }
+
+    #[test]
+    fn test_from_library() {
+        assert_eq!(Term::from_library("PFAM"), Term::Pfam);
+        assert_eq!(
+            Term::from_library("PROSITE_PROFILES"),
+            Term::ProSiteProfiles
+        );
+        assert_eq!(Term::from_library("MOBIDB_LITE"), Term::MobiDBLite);
+        assert_eq!(
+            Term::from_library("FUNFAM"),
+            Term::Other("FunFam".to_string())
+        );
+        assert_eq!(Term::from_library("SIGNALP_EUK").to_string(), "SignalP_EUK");
+        // libraries added to InterProScan later are kept by their name
+        assert_eq!(
+            Term::from_library("NEWLIB"),
+            Term::Other("NEWLIB".to_string())
+        );
+    }
+}
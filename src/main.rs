@@ -1,85 +1,358 @@
 mod args;
+mod errors;
+mod fasta;
 mod gff3;
+mod json;
 mod parser;
 mod partition;
 mod records;
+mod tsv;
+mod xml;
 
-use std::fs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Result};
-use args::OutFormat;
+use args::{InputFormat, OnError, OutFormat};
 use polars::{
-    chunked_array::ChunkedArray,
-    datatypes::{BooleanType, Utf8Chunked},
+    datatypes::{BooleanChunked, Utf8Chunked},
+    prelude::{DataFrame, JoinType, Series},
 };
 use structopt::StructOpt;
 
 use crate::{
     args::{Opt, SubCommands},
-    parser::Expr,
+    errors::ErrorHandler,
+    parser::{Expr, Gene, Hit},
     partition::PartitionedIpcReader,
-    records::Term,
+    records::{
+        domain_record_schema, gene_records_schema, Catalog, DomainRecords, GeneRecords, Organism,
+        Term, TermRegistry,
+    },
 };
 
+fn read_input(
+    input: &Path,
+    input_format: InputFormat,
+    fasta: Option<PathBuf>,
+    organism: Organism,
+    errors: ErrorHandler,
+    domain_records: &mut DomainRecords,
+    gene_records: &mut GeneRecords,
+) -> Result<()> {
+    match input_format {
+        InputFormat::Gff3 => gff3::Reader::from_path(&input)?
+            .with_fasta(fasta)
+            .with_organism(organism)
+            .with_error_handler(errors)
+            .finish(domain_records, gene_records),
+        InputFormat::Tsv => tsv::Reader::from_path(&input)?
+            .with_fasta(fasta)
+            .with_organism(organism)
+            .with_error_handler(errors)
+            .finish(domain_records, gene_records),
+        InputFormat::Json => json::Reader::from_path(&input)?
+            .with_organism(organism)
+            .finish(domain_records, gene_records),
+        InputFormat::Xml => xml::Reader::from_path(&input)?
+            .with_organism(organism)
+            .finish(domain_records, gene_records),
+    }
+}
+
+/// Evaluate `expr` on each gene of the domain table grouped by gene, joined with the gene table
+fn matches(expr: &Expr, df: &DataFrame) -> Result<BooleanChunked> {
+    let column =
+        |name: &str| -> Result<Vec<Option<Series>>> { Ok(df[name].list()?.into_iter().collect()) };
+    let names = column("domain_name_agg_list")?;
+    let sources = column("source_agg_list")?;
+    let descs = column("domain_desc_agg_list")?;
+    let starts = column("start_agg_list")?;
+    let ends = column("end_agg_list")?;
+    let domain_orgs = column("org_agg_list")?;
+    let lengths = df["length"].i32()?;
+    let gene_descs = df["desc"].utf8()?;
+    let orgs = df["org"].utf8()?;
+    let seqs = df["seq"].utf8()?;
+
+    (0..df.height())
+        .map(|i| {
+            let hits: Vec<Hit> = match (&names[i], &sources[i], &descs[i], &starts[i], &ends[i]) {
+                (Some(names), Some(sources), Some(descs), Some(starts), Some(ends)) => names
+                    .utf8()?
+                    .into_iter()
+                    .zip(sources.utf8()?.into_iter())
+                    .zip(descs.utf8()?.into_iter())
+                    .zip(starts.i32()?.into_iter().zip(ends.i32()?.into_iter()))
+                    .filter_map(|(((name, source), desc), (start, end))| {
+                        Some(Hit {
+                            name: name?,
+                            source,
+                            desc,
+                            start: start?,
+                            end: end?,
+                            length: lengths.get(i),
+                        })
+                    })
+                    .collect(),
+                // genes without domains in the selected partitions
+                _ => Vec::new(),
+            };
+
+            // genes without a row in the gene table only have the org of their domains
+            let org = match orgs.get(i) {
+                Some(org) => Some(org),
+                None => match &domain_orgs[i] {
+                    Some(domain_orgs) => domain_orgs.utf8()?.get(0),
+                    None => None,
+                },
+            };
+            let gene = Gene {
+                length: lengths.get(i),
+                desc: gene_descs.get(i),
+                org,
+                seq: seqs.get(i),
+            };
+
+            expr.matches_gene(&gene, &hits)
+                .map_err(|e| anyhow!(e.to_string()))
+        })
+        .collect()
+}
+
+/// Sources of the domain partitions `find` reads for `expr`, `None` for all of them
+fn domain_sources(dir: &Path, org: &Option<Vec<String>>, expr: &Expr) -> Result<Option<Vec<Term>>> {
+    let mut sources = match Catalog::read(dir, org.to_owned())? {
+        Some(catalog) => Some(catalog.sources_of(&expr.names(), &expr.patterns())),
+        // databases registered before catalogs were written
+        None => TermRegistry::from_dir(dir)?.infer_expr(expr),
+    };
+    if expr.needs_all_sources() {
+        sources = None;
+    }
+    // qualified names select their partitions directly
+    if let Some(sources) = sources.as_mut() {
+        for source in expr.sources() {
+            let source = Term::from_str(source)?;
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+    }
+
+    Ok(sources)
+}
+
+/// Genes of `gene_df` and `domain_df` matching `expr`, one row per gene.
+/// `domain_df` holds the domain rows with the `source` and `org` partition columns.
+fn find_genes(expr: &Expr, domain_df: &DataFrame, gene_df: &DataFrame) -> Result<DataFrame> {
+    // coordinates are kept for the order of domains
+    let domain_df = domain_df
+        .select([
+            "gene_id",
+            "domain_name",
+            "domain_desc",
+            "source",
+            "start",
+            "end",
+            "org",
+        ])?
+        .groupby(["gene_id"])?
+        .agg_list()?;
+
+    // evaluate every gene of both tables, so negations also match genes without domains
+    // and genes without a gene row (e.g. GFF3 registered without sequences) are kept
+    let df = gene_df
+        .select(["gene_id", "length", "desc", "org", "seq"])?
+        .join(&domain_df, ["gene_id"], ["gene_id"], JoinType::Outer, None)?;
+
+    let mask = matches(expr, &df)?;
+
+    Ok(df.filter(&mask)?)
+}
+
+/// `start-end` of each match of the motifs in `expr`, for each gene of `df` with the `seq` column.
+/// Matches of a motif are joined by `,` after the motif, and motifs are joined by `;`,
+/// e.g. `/C..C/:4-7,20-23;C-x(2)-H:12-30`.
+fn motif_positions(expr: &Expr, df: &DataFrame) -> Result<Vec<String>> {
+    let motifs = expr.motifs();
+
+    Ok(df["seq"]
+        .utf8()?
+        .into_iter()
+        .map(|seq| {
+            motifs
+                .iter()
+                .map(|motif| {
+                    let positions = motif
+                        .positions(seq.unwrap_or_default())
+                        .iter()
+                        .map(|(start, end)| format!("{}-{}", start, end))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    format!("{}:{}", motif.as_str(), positions)
+                })
+                .collect::<Vec<String>>()
+                .join(";")
+        })
+        .collect())
+}
+
 fn main() -> Result<()> {
     let opt = Opt::from_args();
 
     match &opt.subcommands {
-        SubCommands::Register { input, org, dir } => {
+        SubCommands::Register {
+            input,
+            input_format,
+            fasta,
+            org,
+            taxon_id,
+            strain,
+            assembly,
+            dir,
+            on_error,
+            quarantine_file,
+        } => {
             let orgname = format!("org={}", org);
             let domain_dir = dir.join("domain").join(&orgname);
 
             if domain_dir.exists() {
                 return Err(anyhow!(format!("{} is already registered", org)));
             }
-            let (dr, gr) = gff3::Reader::from_path(input)?.finish()?;
-            let orgname = format!("org={}", org);
-            let domain_dir = dir.join("domain").join(&orgname);
+            let input_format = match input_format {
+                Some(f) => *f,
+                None => InputFormat::infer(input).ok_or_else(|| {
+                    anyhow!(format!(
+                        "Can't infer the format of {}, use --input-format",
+                        input.display()
+                    ))
+                })?,
+            };
+            // JSON and XML carry their own sequences
+            if fasta.is_some() && matches!(input_format, InputFormat::Json | InputFormat::Xml) {
+                return Err(anyhow!(format!(
+                    "--fasta is only used for GFF3 and TSV input, not {:?}",
+                    input_format
+                )));
+            }
+            // records of JSON and XML are parsed as a whole document, so there are no lines to skip
+            if *on_error != OnError::Fail
+                && matches!(input_format, InputFormat::Json | InputFormat::Xml)
+            {
+                return Err(anyhow!(format!(
+                    "--on-error {} is only supported for GFF3 and TSV input, not {:?}",
+                    on_error, input_format
+                )));
+            }
 
-            dr.write(domain_dir)?;
+            let organism = Organism {
+                name: org.clone(),
+                taxon_id: *taxon_id,
+                strain: strain.clone(),
+                assembly: assembly.clone(),
+            };
 
             let mut gene_path = dir.join("gene").join(&orgname);
-
             fs::create_dir_all(&gene_path)?;
             gene_path.push(format!("{}.ipc", uuid::Uuid::new_v4()));
-            gr.write(&gene_path)?;
+
+            let quarantine_path = quarantine_file
+                .clone()
+                .unwrap_or_else(|| dir.join("quarantine").join(format!("{}.tsv", org)));
+
+            let catalog_path = dir
+                .join("catalog")
+                .join(&orgname)
+                .join(format!("{}.ipc", uuid::Uuid::new_v4()));
+
+            let mut dr =
+                DomainRecords::new(10000, domain_dir.clone()).with_catalog(catalog_path.clone());
+            let mut gr = GeneRecords::new(5000, &gene_path)?;
+
+            let result = read_input(
+                input,
+                input_format,
+                fasta.clone(),
+                organism,
+                ErrorHandler::new(*on_error, quarantine_path),
+                &mut dr,
+                &mut gr,
+            )
+            .and_then(|_| dr.finish())
+            .and_then(|_| gr.finish());
+
+            // records are written while reading, so don't leave a half registered organism
+            if result.is_err() {
+                let _ = fs::remove_dir_all(&domain_dir);
+                let _ = fs::remove_file(&gene_path);
+                let _ = fs::remove_file(&catalog_path);
+            }
+            result?;
         }
         SubCommands::Find {
             dir,
             expr,
             org,
             format,
+            max_evalue,
+            motif_positions: with_motif_positions,
         } => {
             let format = format.as_ref().unwrap_or(&args::OutFormat::Id);
-            let sources = Term::try_from_expr(expr)?;
+            // registered sources qualify names too, including those without a `Term` variant
+            let registered = PartitionedIpcReader::new(dir.join("domain"))
+                .with_org(org.to_owned())
+                .partition_values("source")?;
+            let registered: Vec<&str> = registered.iter().map(|s| s.as_str()).collect();
+            let parsed_expr = Expr::from_string_with_sources(expr, &registered)
+                .map_err(|e| anyhow!(e.to_string()))?;
+            let sources = domain_sources(dir, org, &parsed_expr)?;
 
             let mut domain_dir = dir.clone();
             domain_dir.push("domain");
-            let domain_df = PartitionedIpcReader::new(domain_dir)
+            let mut domain_df = PartitionedIpcReader::new(domain_dir)
+                .with_schema(domain_record_schema())
                 .with_org(org.to_owned())
-                .with_source(Some(sources))
-                .finish()?
-                .groupby(["gene_id"])?
-                .agg_list()?;
-
-            let expr = Expr::from_string(expr).unwrap();
-            let mask: ChunkedArray<BooleanType> = domain_df["domain_name_agg_list"]
-                .list()?
-                .into_iter()
-                .map(|l| {
-                    if let Some(s) = l {
-                        let s: Vec<&str> = s.0.utf8().unwrap().into_iter().flatten().collect();
-                        let bool = expr.matches(&s).unwrap();
-                        Ok(bool)
-                    } else {
-                        Ok(false)
-                    }
-                })
-                .collect::<Result<_>>()?;
+                .with_source(sources)
+                .with_partition_columns(&["source", "org"])
+                .finish()?;
 
-            let df = domain_df.filter(&mask)?;
+            if let Some(max_evalue) = max_evalue {
+                let mask: BooleanChunked = domain_df["evalue"]
+                    .f64()?
+                    .into_iter()
+                    .map(|evalue| evalue.map_or(true, |evalue| evalue <= *max_evalue))
+                    .collect();
+                domain_df = domain_df.filter(&mask)?;
+            }
+
+            let gene_df = PartitionedIpcReader::new(dir.join("gene"))
+                .with_schema(gene_records_schema())
+                .with_org(org.to_owned())
+                .with_partition_columns(&["org"])
+                .finish()?;
+
+            let df = find_genes(&parsed_expr, &domain_df, &gene_df)?;
 
             match format {
+                OutFormat::Id if *with_motif_positions => {
+                    let positions = motif_positions(&parsed_expr, &df)?;
+                    println!(
+                        "{}",
+                        df["gene_id"]
+                            .utf8()?
+                            .into_iter()
+                            .zip(positions.iter())
+                            .filter_map(|(gene_id, positions)| {
+                                Some(format!("{}\t{}", gene_id?, positions))
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n")
+                    )
+                }
                 OutFormat::Id => {
                     println!(
                         "{}",
@@ -93,23 +366,20 @@ fn main() -> Result<()> {
                     )
                 }
                 OutFormat::Fasta => {
-                    let gene_dir = dir.join("gene");
-
-                    let gene_df = PartitionedIpcReader::new(gene_dir)
-                        .with_org(org.to_owned())
-                        .finish()?;
                     let gene_df = df
-                        .join(
-                            &gene_df,
-                            ["gene_id"],
-                            ["gene_id"],
-                            polars::prelude::JoinType::Inner,
-                            None,
-                        )?
+                        .select(["gene_id"])?
+                        .join(&gene_df, ["gene_id"], ["gene_id"], JoinType::Inner, None)?
                         .select(["gene_id", "seq"])?;
                     let len = gene_df.height();
-                    let header = &Utf8Chunked::from_iter(std::iter::repeat(">").take(len))
+                    let mut header = &Utf8Chunked::from_iter(std::iter::repeat(">").take(len))
                         + gene_df["gene_id"].utf8()?;
+                    if *with_motif_positions {
+                        let positions = motif_positions(&parsed_expr, &gene_df)?;
+                        header = header
+                            + Utf8Chunked::from_iter(
+                                positions.iter().map(|p| format!(" motif={}", p)),
+                            );
+                    }
                     let header_with_n =
                         header + Utf8Chunked::from_iter(std::iter::repeat("\n").take(len));
                     let fasta = &header_with_n + gene_df["seq"].utf8()?;
@@ -129,3 +399,167 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test_main {
+    use super::*;
+    use crate::records::CatalogRecords;
+    use polars::df;
+
+    fn gene_ids(df: &DataFrame) -> Vec<&str> {
+        let mut ids: Vec<&str> = df["gene_id"]
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn test_find_genes() {
+        let domain_df = df!(
+            "gene_id" => &["g1", "g1", "g3"],
+            "domain_name" => &["PF00069", "IPR000719", "PF00069"],
+            "domain_desc" => &[Some("Protein kinase domain"), None, None],
+            "source" => &["Pfam", "InterPro", "Pfam"],
+            "start" => &[10i32, 10, 5],
+            "end" => &[250i32, 250, 200],
+            "org" => &["Athaliana", "Athaliana", "Athaliana"]
+        )
+        .unwrap();
+        // g2 has no domains, g3 has domains but no gene row (e.g. GFF3 without sequences)
+        let gene_df = df!(
+            "gene_id" => &["g1", "g2"],
+            "length" => &[300i32, 120],
+            "desc" => &[None::<&str>, None],
+            "org" => &["Athaliana", "Athaliana"],
+            "seq" => &["MKV", "MAL"]
+        )
+        .unwrap();
+
+        let find = |s: &str| {
+            let expr = Expr::from_string(s).unwrap();
+            find_genes(&expr, &domain_df, &gene_df).unwrap()
+        };
+        assert_eq!(gene_ids(&find("PF00069")), vec!["g1", "g3"]);
+        assert_eq!(gene_ids(&find("!PF00069")), vec!["g2"]);
+        assert_eq!(gene_ids(&find("!IPR000719")), vec!["g2", "g3"]);
+        assert_eq!(gene_ids(&find("PF00069 & org=Athaliana")), vec!["g1", "g3"]);
+        assert_eq!(gene_ids(&find("len>200")), vec!["g1"]);
+        assert_eq!(gene_ids(&find("")), vec!["g1", "g2", "g3"]);
+    }
+
+    #[test]
+    fn test_find_genes_pruned() {
+        // a kinase followed by a PH domain, each with an InterPro cross reference at its position
+        let domain_df = df!(
+            "gene_id" => &["g1", "g1", "g1", "g1"],
+            "domain_name" => &["PF00069", "IPR000719", "PF00169", "IPR001849"],
+            "domain_desc" => &[None::<&str>, None, None, None],
+            "source" => &["Pfam", "InterPro", "Pfam", "InterPro"],
+            "start" => &[10i32, 10, 300, 300],
+            "end" => &[250i32, 250, 400, 400],
+            "org" => &["A", "A", "A", "A"]
+        )
+        .unwrap();
+        let gene_df = df!(
+            "gene_id" => &["g1"],
+            "length" => &[500i32],
+            "desc" => &[None::<&str>],
+            "org" => &["A"],
+            "seq" => &["MKV"]
+        )
+        .unwrap();
+
+        // without a catalog, the sources are inferred from the names
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("domain").join("org=A")).unwrap();
+
+        // the genes found in the partitions `find` reads, and in all of them
+        let find = |s: &str| {
+            let expr = Expr::from_string(s).unwrap();
+            let pruned = match domain_sources(dir.path(), &None, &expr).unwrap() {
+                Some(sources) => {
+                    let mask: BooleanChunked = domain_df["source"]
+                        .utf8()
+                        .unwrap()
+                        .into_iter()
+                        .map(|source| {
+                            sources
+                                .iter()
+                                .any(|s| Some(s.to_string().as_str()) == source)
+                        })
+                        .collect();
+                    domain_df.filter(&mask).unwrap()
+                }
+                None => domain_df.clone(),
+            };
+            let pruned = find_genes(&expr, &pruned, &gene_df).unwrap();
+            let all = find_genes(&expr, &domain_df, &gene_df).unwrap();
+            assert_eq!(gene_ids(&pruned), gene_ids(&all), "{}", s);
+            gene_ids(&all).len()
+        };
+
+        assert_eq!(find("PF00069 -> PF00169"), 1);
+        assert_eq!(find("PF00169 -> PF00069"), 0);
+        // IPR001849 isn't PF00169
+        assert_eq!(find("PF00069 -> !PF00169"), 1);
+        assert_eq!(find("PF00069 -> !PF00169 | desc~\"x\""), 1);
+        assert_eq!(find("!PF00069 -> PF00169"), 1);
+        assert_eq!(find("PF00069 <overlaps> !PF00069"), 1);
+        assert_eq!(find("PF00069 <in> !PF00069 | desc~\"x\""), 1);
+        assert_eq!(find("PF00169 <50> !PF00169"), 1);
+    }
+
+    #[test]
+    fn test_motif_positions() {
+        let df = df!(
+            "gene_id" => &["g1", "g2", "g3"],
+            "seq" => &[Some("MCAACHCGGC"), Some("MKV"), None]
+        )
+        .unwrap();
+
+        let expr = Expr::from_string("seq~/C..C/ | seq~\"C-x-C\"").unwrap();
+        assert_eq!(
+            motif_positions(&expr, &df).unwrap(),
+            vec![
+                "/C..C/:2-5,7-10;C-x-C:5-7",
+                "/C..C/:;C-x-C:",
+                "/C..C/:;C-x-C:"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_domain_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut catalog = CatalogRecords::default();
+        catalog.push(&Term::Other("Custom".to_string()), "PF00069");
+        catalog
+            .write(&dir.path().join("catalog").join("org=A").join("catalog.ipc"))
+            .unwrap();
+        fs::create_dir_all(dir.path().join("domain").join("org=A")).unwrap();
+        // registered before catalogs were written
+        fs::create_dir_all(dir.path().join("domain").join("org=B")).unwrap();
+
+        let sources = |s: &str, org: Option<Vec<String>>| {
+            let expr = Expr::from_string(s).unwrap();
+            domain_sources(dir.path(), &org, &expr).unwrap()
+        };
+        let a = || Some(vec!["A".to_string()]);
+
+        assert_eq!(
+            sources("PF00069", a()),
+            Some(vec![Term::Other("Custom".to_string())])
+        );
+        // B isn't covered by the catalog, so the sources are inferred from the names
+        assert_eq!(sources("PF00069", None), Some(vec![Term::Pfam]));
+        assert_eq!(
+            sources("PF00069 | SMART:SM00220", a()),
+            Some(vec![Term::Other("Custom".to_string()), Term::SMART])
+        );
+        assert_eq!(sources("PF00069 & desc~\"kinase\"", a()), None);
+    }
+}
@@ -1,12 +1,140 @@
-use super::lex::Token;
+use super::{
+    gene::{Gene, GenePredicate},
+    lex::Token,
+    location::{parse_suffix, Location},
+    motif::Motif,
+    pattern::NamePattern,
+};
+use crate::records::Term;
 use std::{collections::VecDeque, error::Error, fmt};
 
+/// A domain of a gene, as seen by expressions
+#[derive(Debug, Clone, Default)]
+pub struct Hit<'a> {
+    pub name: &'a str,
+    pub source: Option<&'a str>,
+    pub desc: Option<&'a str>,
+    pub start: i32,
+    pub end: i32,
+    /// length of the protein
+    pub length: Option<i32>,
+}
+
+#[cfg(test)]
+impl<'a> Hit<'a> {
+    pub fn new(name: &'a str) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Node {
     Invert(Box<Node>),
-    And { lhs: Box<Node>, rhs: Box<Node> },
-    Or { lhs: Box<Node>, rhs: Box<Node> },
+    And {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+    Or {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
     Name(String),
+    Count {
+        name: String,
+        count: Count,
+    },
+    /// glob or regex, matching any name if `count` is `None`
+    Pattern {
+        pattern: NamePattern,
+        count: Option<Count>,
+    },
+    /// `desc~"text"` or `desc~/regex/` on domain descriptions
+    Desc {
+        pattern: NamePattern,
+        count: Option<Count>,
+    },
+    /// domains of `node` at `locations` (e.g. `a@nterm(40)`), matching any domain if `count` is `None`
+    Located {
+        node: Box<Node>,
+        locations: Vec<Location>,
+        count: Option<Count>,
+    },
+    /// a predicate on the gene, e.g. `len>500`
+    Gene(GenePredicate),
+    /// `a -> b -> ...`, domains matching each step, each starting after the previous one
+    Order(Vec<Node>),
+    /// `a <relation> b`, a pair of different domains matching `lhs` and `rhs` in `relation`
+    Spatial {
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+        relation: Relation,
+    },
+    /// `Source:atom`, which only sees the domains of `source`
+    Source {
+        source: String,
+        node: Box<Node>,
+    },
+}
+
+/// Condition on the number of a domain, written after `$` (e.g. `a$2`, `a$>=2`, `a$2..4`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Count {
+    Eq(usize),
+    Lt(usize),
+    Le(usize),
+    Gt(usize),
+    Ge(usize),
+    /// inclusive on both ends
+    Range(usize, usize),
+}
+
+impl Count {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || -> Box<dyn Error> {
+            Box::new(ParseError::new(format!(
+                "invalid count `{}`, expected one of N, <N, <=N, >N, >=N or N..M",
+                s
+            )))
+        };
+        let number = |n: &str| n.trim().parse::<usize>().map_err(|_| invalid());
+
+        let count = if let Some(n) = s.strip_prefix(">=") {
+            Count::Ge(number(n)?)
+        } else if let Some(n) = s.strip_prefix("<=") {
+            Count::Le(number(n)?)
+        } else if let Some(n) = s.strip_prefix('>') {
+            Count::Gt(number(n)?)
+        } else if let Some(n) = s.strip_prefix('<') {
+            Count::Lt(number(n)?)
+        } else if let Some((lo, hi)) = s.split_once("..") {
+            let (lo, hi) = (number(lo)?, number(hi)?);
+            if lo > hi {
+                return Err(Box::new(ParseError::new(format!(
+                    "invalid count `{}`, the lower bound is larger than the upper bound",
+                    s
+                ))));
+            }
+            Count::Range(lo, hi)
+        } else {
+            Count::Eq(number(s)?)
+        };
+
+        Ok(count)
+    }
+
+    pub fn contains(&self, n: usize) -> bool {
+        match *self {
+            Count::Eq(x) => n == x,
+            Count::Lt(x) => n < x,
+            Count::Le(x) => n <= x,
+            Count::Gt(x) => n > x,
+            Count::Ge(x) => n >= x,
+            Count::Range(lo, hi) => lo <= n && n <= hi,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -30,8 +158,131 @@ impl fmt::Display for ParseError {
 
 impl Error for ParseError {}
 
+/// Position of a domain relative to another, written in `<...>` (e.g. `a <50> b`)
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq)]
+pub enum Relation {
+    /// `<N>`, at most N residues between them (overlapping domains are 0 apart)
+    Within(i32),
+    /// `<overlaps>`, sharing at least one residue
+    Overlaps,
+    /// `<in>`, the first is nested inside the second
+    Inside,
+}
+
+impl Relation {
+    pub fn parse(s: &str) -> Result<Self, Box<dyn Error>> {
+        let relation = match s.trim() {
+            "overlaps" => Relation::Overlaps,
+            "in" => Relation::Inside,
+            n => match n.parse::<i32>() {
+                Ok(n) if n >= 0 => Relation::Within(n),
+                _ => {
+                    return Err(Box::new(ParseError::new(format!(
+                        "invalid relation `<{}>`, expected <N>, <overlaps> or <in>",
+                        s
+                    ))))
+                }
+            },
+        };
+
+        Ok(relation)
+    }
+
+    pub fn holds(&self, a: &Hit, b: &Hit) -> bool {
+        match *self {
+            Relation::Within(n) => {
+                let gap = (b.start - a.end).max(a.start - b.end) - 1;
+                gap.max(0) <= n
+            }
+            Relation::Overlaps => a.start <= b.end && b.start <= a.end,
+            Relation::Inside => b.start <= a.start && a.end <= b.end,
+        }
+    }
+}
+
 impl Node {
-    pub fn munch_tokens(tokens: &mut VecDeque<Token>, depth: u16) -> Result<Self, Box<dyn Error>> {
+    /// `name`, `glob` or `/regex/`, followed by an optional `$<count>`
+    /// and optionally qualified by a source (e.g. `Pfam:PF00069`, `Gene3D:*`).
+    /// Besides the built-in sources, `sources` (e.g. the registered sources) can qualify names.
+    /// Names with a colon whose prefix isn't a source (e.g. `GO:0004672`) are kept as is.
+    pub fn from_name(text: &str, sources: &[&str]) -> Result<Self, Box<dyn Error>> {
+        if let Some(predicate) = GenePredicate::parse(text)? {
+            return Ok(Node::Gene(predicate));
+        }
+
+        if let Some((source, name)) = text.split_once(':') {
+            if !text.starts_with('/') && (Term::is_source_name(source) || sources.contains(&source))
+            {
+                let node = Node::from_name(name, sources)?;
+                if let Node::Gene(_) = node {
+                    return Err(Box::new(ParseError::new(format!(
+                        "`{}` is a gene predicate, which can't be qualified by a source",
+                        name
+                    ))));
+                }
+
+                return Ok(Node::Source {
+                    source: source.to_string(),
+                    node: Box::new(node),
+                });
+            }
+        }
+
+        let (node, rest) = if let Some(text) = text.strip_prefix("desc~") {
+            let (pattern, rest) = NamePattern::text_or_regex(text)?;
+            (
+                Node::Desc {
+                    pattern,
+                    count: None,
+                },
+                rest,
+            )
+        } else if text.starts_with('/') {
+            let (pattern, rest) = NamePattern::regex(text)?;
+            (
+                Node::Pattern {
+                    pattern,
+                    count: None,
+                },
+                rest,
+            )
+        } else {
+            let (name, rest) = text.split_at(text.find(['@', '%', '$']).unwrap_or(text.len()));
+            let node = if NamePattern::is_glob(name) {
+                Node::Pattern {
+                    pattern: NamePattern::glob(name)?,
+                    count: None,
+                }
+            } else {
+                Node::Name(name.to_string())
+            };
+            (node, rest)
+        };
+
+        let (locations, count) = parse_suffix(rest)?;
+        if !locations.is_empty() {
+            return Ok(Node::Located {
+                node: Box::new(node),
+                locations,
+                count,
+            });
+        }
+
+        let node = match (node, count) {
+            (Node::Name(name), Some(count)) => Node::Count { name, count },
+            (Node::Pattern { pattern, .. }, count) => Node::Pattern { pattern, count },
+            (Node::Desc { pattern, .. }, count) => Node::Desc { pattern, count },
+            (node, _) => node,
+        };
+
+        Ok(node)
+    }
+
+    pub fn munch_tokens(
+        tokens: &mut VecDeque<Token>,
+        sources: &[&str],
+        depth: u16,
+    ) -> Result<Self, Box<dyn Error>> {
         if depth == 0 {
             let err: Box<dyn Error> = Box::new(ParseError::new("Expression too deep"));
             return Err(err);
@@ -49,7 +300,7 @@ impl Node {
             Token::CloseBracket => Err(Box::new(ParseError::new("Unexpected closing bracket"))),
             Token::OpenBracket => {
                 let _ = tokens.pop_front();
-                let result = Self::munch_tokens(tokens, depth - 1)?;
+                let result = Self::munch_tokens(tokens, sources, depth - 1)?;
 
                 if let Some(tk) = tokens.pop_front() {
                     if tk != Token::CloseBracket {
@@ -59,41 +310,27 @@ impl Node {
                     }
                 }
 
-                return match tokens.front() {
-                    Some(Token::And) => {
-                        tokens.pop_front();
-                        let result = Node::And {
-                            lhs: Box::new(result),
-                            rhs: Box::new(Self::munch_tokens(tokens, depth - 1)?),
-                        };
-                        return Ok(result);
-                    }
-                    Some(Token::Or) => {
-                        let _ = tokens.pop_front();
-                        let result = Node::Or {
-                            lhs: Box::new(result),
-                            rhs: Box::new(Self::munch_tokens(tokens, depth - 1)?),
-                        };
-                        return Ok(result);
-                    }
-                    None | Some(Token::CloseBracket) => Ok(result),
-                    Some(_) => {
-                        let err: Box<dyn Error> =
-                            Box::new(ParseError::new("invald token after closing bracket"));
-                        return Err(err);
-                    }
-                };
+                Self::munch_after(result, tokens, sources, depth)
             }
             Token::Invert => {
                 let _ = tokens.pop_front();
 
                 match tokens.front() {
-                    Some(Token::OpenBracket) => Ok(Node::Invert(Box::new(Self::munch_tokens(
-                        tokens,
-                        depth - 1,
-                    )?))),
+                    Some(Token::OpenBracket) => {
+                        // "!(abc) -> xyz" is "(!(abc)) -> xyz" as "!abc -> xyz" is
+                        let _ = tokens.pop_front();
+                        let inverted = Self::munch_tokens(tokens, sources, depth - 1)?;
+                        match tokens.pop_front() {
+                            Some(Token::CloseBracket) | None => {}
+                            Some(_) => {
+                                return Err(Box::new(ParseError::new("expected closing bracket")))
+                            }
+                        }
+
+                        Self::munch_after(Node::Invert(Box::new(inverted)), tokens, sources, depth)
+                    }
                     Some(Token::Name(text)) => {
-                        let inverted = Node::Invert(Box::new(Node::Name(text.clone())));
+                        let node = Node::from_name(text, sources)?;
                         match tokens.get(1) {
                             Some(Token::And) | Some(Token::Or) => {
                                 // "!abc & xyz"
@@ -103,12 +340,23 @@ impl Node {
                                 tokens.insert(2, Token::OpenBracket);
                                 tokens.insert(4, Token::CloseBracket);
                                 tokens.insert(5, Token::CloseBracket);
-                                Self::munch_tokens(tokens, depth - 1)
+                                Self::munch_tokens(tokens, sources, depth - 1)
                             }
                             None | Some(Token::CloseBracket) => {
                                 // "!abc"
                                 tokens.remove(0); // remove name
-                                Ok(inverted)
+                                Ok(Node::Invert(Box::new(node)))
+                            }
+                            Some(Token::Then) | Some(Token::Spatial(_)) => {
+                                // "!abc -> xyz" is "(!abc) -> xyz" as in "xyz -> !abc",
+                                // while "!(abc -> xyz)" inverts the whole relation
+                                tokens.remove(0); // remove name
+                                Self::munch_after(
+                                    Node::Invert(Box::new(node)),
+                                    tokens,
+                                    sources,
+                                    depth,
+                                )
                             }
                             Some(_) => Err(Box::new(ParseError::new(
                                 "invalid token after inverted name",
@@ -127,40 +375,405 @@ impl Node {
             Token::Name(text) => match tokens.get(1) {
                 Some(Token::And) | Some(Token::Or) => {
                     add_bracket(tokens);
-                    Self::munch_tokens(tokens, depth - 1)
+                    Self::munch_tokens(tokens, sources, depth - 1)
                 }
                 Some(Token::CloseBracket) | None => {
-                    let text = text.clone();
+                    let node = Node::from_name(text, sources)?;
+                    let _ = tokens.pop_front();
+                    Ok(node)
+                }
+                Some(Token::Then) | Some(Token::Spatial(_)) => {
+                    let node = Node::from_name(text, sources)?;
                     let _ = tokens.pop_front();
-                    Ok(Node::Name(text))
+                    Self::munch_after(node, tokens, sources, depth)
                 }
                 Some(_) => Err(Box::new(ParseError::new("Name followed by invalid token"))),
             },
-            Token::And | Token::Or => Err(Box::new(ParseError::new("Unexpected binary operator"))),
+            Token::And | Token::Or | Token::Then | Token::Spatial(_) => {
+                Err(Box::new(ParseError::new("Unexpected binary operator")))
+            }
+        }
+    }
+
+    /// Combine `lhs` with the operator following it
+    fn munch_after(
+        lhs: Node,
+        tokens: &mut VecDeque<Token>,
+        sources: &[&str],
+        depth: u16,
+    ) -> Result<Self, Box<dyn Error>> {
+        match tokens.front() {
+            Some(Token::And) => {
+                tokens.pop_front();
+                Ok(Node::And {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(Self::munch_tokens(tokens, sources, depth - 1)?),
+                })
+            }
+            Some(Token::Or) => {
+                tokens.pop_front();
+                Ok(Node::Or {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(Self::munch_tokens(tokens, sources, depth - 1)?),
+                })
+            }
+            Some(Token::Then) | Some(Token::Spatial(_)) => {
+                let relation = Self::munch_relation(lhs, tokens, sources, depth)?;
+                Self::munch_after(relation, tokens, sources, depth)
+            }
+            None | Some(Token::CloseBracket) => Ok(lhs),
+            Some(_) => Err(Box::new(ParseError::new("invalid token after expression"))),
+        }
+    }
+
+    /// `lhs -> b -> c ...` or `lhs <relation> b`, binding tighter than `&` and `|`
+    fn munch_relation(
+        lhs: Node,
+        tokens: &mut VecDeque<Token>,
+        sources: &[&str],
+        depth: u16,
+    ) -> Result<Self, Box<dyn Error>> {
+        let node = match tokens.front() {
+            Some(Token::Then) => {
+                let mut steps = vec![lhs];
+                while tokens.front() == Some(&Token::Then) {
+                    tokens.pop_front();
+                    steps.push(Self::munch_step(tokens, sources, depth - 1)?);
+                }
+                Node::Order(steps)
+            }
+            Some(Token::Spatial(relation)) => {
+                let relation = relation.clone();
+                tokens.pop_front();
+                Node::Spatial {
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(Self::munch_step(tokens, sources, depth - 1)?),
+                    relation,
+                }
+            }
+            _ => return Ok(lhs),
+        };
+
+        let operands: Vec<&Node> = match &node {
+            Node::Order(steps) => steps.iter().collect(),
+            Node::Spatial { lhs, rhs, .. } => vec![lhs, rhs],
+            _ => Vec::new(),
+        };
+        for operand in operands {
+            if !operand.is_domain_predicate() {
+                return Err(Box::new(ParseError::new(format!(
+                    "{:?} can't be compared by position, only names, patterns and desc~ without counts",
+                    operand
+                ))));
+            }
+        }
+
+        Ok(node)
+    }
+
+    /// A name, a bracket or an inverted step after `->` or `<relation>`
+    fn munch_step(
+        tokens: &mut VecDeque<Token>,
+        sources: &[&str],
+        depth: u16,
+    ) -> Result<Self, Box<dyn Error>> {
+        if depth == 0 {
+            return Err(Box::new(ParseError::new("Expression too deep")));
+        }
+
+        match tokens.pop_front() {
+            Some(Token::Name(text)) => Node::from_name(&text, sources),
+            Some(Token::OpenBracket) => {
+                let node = Self::munch_tokens(tokens, sources, depth - 1)?;
+                match tokens.pop_front() {
+                    Some(Token::CloseBracket) => Ok(node),
+                    _ => Err(Box::new(ParseError::new("expected closing bracket"))),
+                }
+            }
+            Some(Token::Invert) => Ok(Node::Invert(Box::new(Self::munch_step(
+                tokens,
+                sources,
+                depth - 1,
+            )?))),
+            _ => Err(Box::new(ParseError::new("expected a domain after ->"))),
+        }
+    }
+
+    /// Whether the node can be evaluated on a single domain (see `matches_hit`)
+    fn is_domain_predicate(&self) -> bool {
+        match self {
+            Self::Name(_) | Self::Pattern { count: None, .. } | Self::Desc { count: None, .. } => {
+                true
+            }
+            Self::Source { node, .. }
+            | Self::Invert(node)
+            | Self::Located {
+                node, count: None, ..
+            } => node.is_domain_predicate(),
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.is_domain_predicate() && rhs.is_domain_predicate()
+            }
+            Self::Count { .. }
+            | Self::Pattern { .. }
+            | Self::Desc { .. }
+            | Self::Located { .. }
+            | Self::Gene(_)
+            | Self::Order(_)
+            | Self::Spatial { .. } => false,
+        }
+    }
+
+    /// Whether a domain predicate inverts an unqualified part, matching domains of any source
+    fn has_inverted_domain(&self) -> bool {
+        match self {
+            Self::Invert(_) => true,
+            Self::Located { node, .. } => node.has_inverted_domain(),
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.has_inverted_domain() || rhs.has_inverted_domain()
+            }
+            // qualified atoms only see the domains of their source, which is always read
+            Self::Source { .. }
+            | Self::Name(_)
+            | Self::Count { .. }
+            | Self::Pattern { .. }
+            | Self::Desc { .. }
+            | Self::Gene(_)
+            | Self::Order(_)
+            | Self::Spatial { .. } => false,
         }
     }
 
+    /// Evaluate a domain predicate on a single domain
+    fn matches_hit(&self, hit: &Hit) -> bool {
+        match self {
+            Self::Name(name) => hit.name == name.as_str(),
+            Self::Pattern { pattern, .. } => pattern.is_match(hit.name),
+            Self::Desc { pattern, .. } => matches!(hit.desc, Some(desc) if pattern.is_match(desc)),
+            Self::Source { source, node } => {
+                hit.source == Some(source.as_str()) && node.matches_hit(hit)
+            }
+            Self::Invert(node) => !node.matches_hit(hit),
+            Self::Located {
+                node, locations, ..
+            } => node.matches_hit(hit) && locations.iter().all(|l| l.holds(hit)),
+            Self::And { lhs, rhs } => lhs.matches_hit(hit) && rhs.matches_hit(hit),
+            Self::Or { lhs, rhs } => lhs.matches_hit(hit) || rhs.matches_hit(hit),
+            // rejected by `is_domain_predicate` on parsing
+            Self::Count { .. } | Self::Gene(_) | Self::Order(_) | Self::Spatial { .. } => false,
+        }
+    }
+
+    /// Evaluate the tree on domains known only by name
+    #[cfg(test)]
     pub fn matches(&self, tags: &[&str]) -> Result<bool, Box<dyn Error>> {
+        let hits: Vec<Hit> = tags.iter().map(|tag| Hit::new(tag)).collect();
+        self.matches_hits(&hits)
+    }
+
+    /// Evaluate the tree on domains of an unknown gene
+    #[cfg(test)]
+    pub fn matches_hits(&self, hits: &[Hit]) -> Result<bool, Box<dyn Error>> {
+        self.matches_gene(&Gene::default(), hits)
+    }
+
+    /// Evaluate the tree on a gene and its domains
+    pub fn matches_gene(&self, gene: &Gene, hits: &[Hit]) -> Result<bool, Box<dyn Error>> {
         let result = match self {
-            Self::Invert(inverted) => !inverted.matches(tags)?,
-            Self::Name(text) => {
+            Self::Invert(inverted) => !inverted.matches_gene(gene, hits)?,
+            Self::Name(text) => hits.iter().any(|hit| hit.name == text.as_str()),
+            Self::Count { name, count } => {
                 // counting numbers of elements
-                let splitted: Vec<&str> = text.split('$').collect();
-                match splitted.len() {
-                    1 => tags.contains(&&**text),
-                    2 => {
-                        let count = splitted[1].parse::<usize>()?;
-                        count == tags.iter().filter(|x| x == &&splitted[0]).count()
-                    }
-                    _ => return Err(Box::new(ParseError::new("unexpected text format"))),
+                count.contains(hits.iter().filter(|hit| hit.name == name.as_str()).count())
+            }
+            Self::Pattern { pattern, count } => {
+                let mut matched = hits.iter().filter(|hit| pattern.is_match(hit.name));
+                match count {
+                    Some(count) => count.contains(matched.count()),
+                    None => matched.next().is_some(),
                 }
             }
-            Self::And { lhs, rhs } => lhs.matches(tags)? && rhs.matches(tags)?,
-            Self::Or { lhs, rhs } => lhs.matches(tags)? || rhs.matches(tags)?,
+            Self::Desc { pattern, count } => {
+                let mut matched = hits
+                    .iter()
+                    .filter(|hit| matches!(hit.desc, Some(desc) if pattern.is_match(desc)));
+                match count {
+                    Some(count) => count.contains(matched.count()),
+                    None => matched.next().is_some(),
+                }
+            }
+            Self::Located {
+                node,
+                locations,
+                count,
+            } => {
+                let mut matched = hits
+                    .iter()
+                    .filter(|hit| node.matches_hit(hit) && locations.iter().all(|l| l.holds(hit)));
+                match count {
+                    Some(count) => count.contains(matched.count()),
+                    None => matched.next().is_some(),
+                }
+            }
+            Self::Source { source, node } => {
+                let hits: Vec<Hit> = hits
+                    .iter()
+                    .filter(|hit| hit.source == Some(source.as_str()))
+                    .cloned()
+                    .collect();
+                node.matches_gene(gene, &hits)?
+            }
+            Self::Gene(predicate) => predicate.holds(gene),
+            Self::Order(steps) => {
+                let mut sorted: Vec<&Hit> = hits.iter().collect();
+                sorted.sort_by_key(|hit| hit.start);
+
+                // each step must start strictly later, so domains sharing a position
+                // (e.g. a signature and its cross references) are never ordered by the row order.
+                // The earliest match of each step leaves the most room for the next steps.
+                let mut last_start = i32::MIN;
+                steps.iter().all(|step| {
+                    match sorted
+                        .iter()
+                        .find(|hit| hit.start > last_start && step.matches_hit(hit))
+                    {
+                        Some(hit) => {
+                            last_start = hit.start;
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            }
+            Self::Spatial { lhs, rhs, relation } => hits.iter().enumerate().any(|(i, a)| {
+                lhs.matches_hit(a)
+                    && hits
+                        .iter()
+                        .enumerate()
+                        .any(|(j, b)| i != j && rhs.matches_hit(b) && relation.holds(a, b))
+            }),
+            Self::And { lhs, rhs } => {
+                lhs.matches_gene(gene, hits)? && rhs.matches_gene(gene, hits)?
+            }
+            Self::Or { lhs, rhs } => {
+                lhs.matches_gene(gene, hits)? || rhs.matches_gene(gene, hits)?
+            }
         };
 
         Ok(result)
     }
+
+    /// Unqualified domain names in the tree, without the `$N` count suffix
+    pub fn names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Self::Invert(inverted) => inverted.names(names),
+            Self::Located { node, .. } => node.names(names),
+            Self::Order(steps) => steps.iter().for_each(|step| step.names(names)),
+            Self::Spatial { lhs, rhs, .. } => {
+                lhs.names(names);
+                rhs.names(names);
+            }
+            Self::Name(name) | Self::Count { name, .. } => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+            Self::Pattern { .. } | Self::Desc { .. } | Self::Source { .. } | Self::Gene(_) => {}
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.names(names);
+                rhs.names(names);
+            }
+        }
+    }
+
+    /// Unqualified glob and regex patterns in the tree
+    pub fn patterns<'a>(&'a self, patterns: &mut Vec<&'a NamePattern>) {
+        match self {
+            Self::Invert(inverted) => inverted.patterns(patterns),
+            Self::Located { node, .. } => node.patterns(patterns),
+            Self::Order(steps) => steps.iter().for_each(|step| step.patterns(patterns)),
+            Self::Spatial { lhs, rhs, .. } => {
+                lhs.patterns(patterns);
+                rhs.patterns(patterns);
+            }
+            Self::Pattern { pattern, .. } => patterns.push(pattern),
+            Self::Name(_)
+            | Self::Count { .. }
+            | Self::Desc { .. }
+            | Self::Source { .. }
+            | Self::Gene(_) => {}
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.patterns(patterns);
+                rhs.patterns(patterns);
+            }
+        }
+    }
+
+    /// Sequence motifs in the tree
+    pub fn motifs<'a>(&'a self, motifs: &mut Vec<&'a Motif>) {
+        match self {
+            Self::Invert(inverted) => inverted.motifs(motifs),
+            Self::Gene(GenePredicate::Motif(motif)) => motifs.push(motif),
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.motifs(motifs);
+                rhs.motifs(motifs);
+            }
+            // motifs aren't domain predicates, so they can't be under the other nodes
+            _ => {}
+        }
+    }
+
+    /// Whether an unqualified atom may match domains of any source (e.g. `desc~"kinase"`),
+    /// so partitions can't be selected by names.
+    /// Inverted operands of orders and relations (e.g. `a -> !b`, `a <in> !b`)
+    /// match domains of any source too.
+    pub fn needs_all_sources(&self) -> bool {
+        match self {
+            Self::Invert(inverted) => inverted.needs_all_sources(),
+            Self::Located { node, .. } => node.needs_all_sources(),
+            Self::Order(steps) => steps
+                .iter()
+                .any(|step| step.needs_all_sources() || step.has_inverted_domain()),
+            Self::Spatial { lhs, rhs, .. } => [lhs, rhs]
+                .iter()
+                .any(|node| node.needs_all_sources() || node.has_inverted_domain()),
+            Self::Desc { .. } => true,
+            Self::Name(_)
+            | Self::Count { .. }
+            | Self::Pattern { .. }
+            | Self::Source { .. }
+            | Self::Gene(_) => false,
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.needs_all_sources() || rhs.needs_all_sources()
+            }
+        }
+    }
+
+    /// Sources qualifying atoms in the tree
+    pub fn sources<'a>(&'a self, sources: &mut Vec<&'a str>) {
+        match self {
+            Self::Invert(inverted) => inverted.sources(sources),
+            Self::Located { node, .. } => node.sources(sources),
+            Self::Order(steps) => steps.iter().for_each(|step| step.sources(sources)),
+            Self::Spatial { lhs, rhs, .. } => {
+                lhs.sources(sources);
+                rhs.sources(sources);
+            }
+            Self::Source { source, .. } => {
+                if !sources.contains(&source.as_str()) {
+                    sources.push(source);
+                }
+            }
+            Self::Name(_)
+            | Self::Count { .. }
+            | Self::Pattern { .. }
+            | Self::Desc { .. }
+            | Self::Gene(_) => {}
+            Self::And { lhs, rhs } | Self::Or { lhs, rhs } => {
+                lhs.sources(sources);
+                rhs.sources(sources);
+            }
+        }
+    }
 }
 
 fn add_bracket(tokens: &mut VecDeque<Token>) {
@@ -1,7 +1,10 @@
 use std::{collections::VecDeque, error::Error};
 
-use super::ast::{Node, ParseError};
+use super::ast::{Hit, Node, ParseError};
+use super::gene::Gene;
 use super::lex::{lex, Token};
+use super::motif::Motif;
+use super::pattern::NamePattern;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum ExprData {
@@ -15,7 +18,14 @@ pub struct Expr(ExprData);
 pub const MAX_RECURSION: u16 = 20;
 
 impl Expr {
+    #[cfg(test)]
     pub fn from_string(s: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_string_with_sources(s, &[])
+    }
+
+    /// Parse with `sources` which can qualify names besides the built-in sources,
+    /// e.g. sources registered as `Term::Other` (`NCBIfam:NF000001`)
+    pub fn from_string_with_sources(s: &str, sources: &[&str]) -> Result<Self, Box<dyn Error>> {
         // lex and convert to a deque
         let mut tokens: VecDeque<Token> = VecDeque::from(lex(s)?);
         if tokens.is_empty() {
@@ -23,7 +33,7 @@ impl Expr {
             return Ok(Self(ExprData::Empty));
         }
 
-        let ast = Node::munch_tokens(&mut tokens, MAX_RECURSION)?;
+        let ast = Node::munch_tokens(&mut tokens, sources, MAX_RECURSION)?;
         if !tokens.is_empty() {
             return Err(Box::new(ParseError::new(
                 "expected EOF, found extra tokens",
@@ -33,6 +43,7 @@ impl Expr {
         Ok(Self(ExprData::HasNodes(ast)))
     }
 
+    #[cfg(test)]
     pub fn matches(&self, tags: &[&str]) -> Result<bool, Box<dyn Error>> {
         match &self.0 {
             ExprData::Empty => Ok(true),
@@ -40,6 +51,64 @@ impl Expr {
         }
     }
 
+    #[cfg(test)]
+    pub fn matches_hits(&self, hits: &[Hit]) -> Result<bool, Box<dyn Error>> {
+        match &self.0 {
+            ExprData::Empty => Ok(true),
+            ExprData::HasNodes(node) => node.matches_hits(hits),
+        }
+    }
+
+    pub fn matches_gene(&self, gene: &Gene, hits: &[Hit]) -> Result<bool, Box<dyn Error>> {
+        match &self.0 {
+            ExprData::Empty => Ok(true),
+            ExprData::HasNodes(node) => node.matches_gene(gene, hits),
+        }
+    }
+
+    /// Domain names used in the expression, without the `$N` count suffix
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        if let ExprData::HasNodes(node) = &self.0 {
+            node.names(&mut names);
+        }
+        names
+    }
+
+    /// Glob and regex patterns used in the expression
+    pub fn patterns(&self) -> Vec<&NamePattern> {
+        let mut patterns = Vec::new();
+        if let ExprData::HasNodes(node) = &self.0 {
+            node.patterns(&mut patterns);
+        }
+        patterns
+    }
+
+    /// Sequence motifs used in the expression
+    pub fn motifs(&self) -> Vec<&Motif> {
+        let mut motifs = Vec::new();
+        if let ExprData::HasNodes(node) = &self.0 {
+            node.motifs(&mut motifs);
+        }
+        motifs
+    }
+
+    pub fn needs_all_sources(&self) -> bool {
+        match &self.0 {
+            ExprData::Empty => false,
+            ExprData::HasNodes(node) => node.needs_all_sources(),
+        }
+    }
+
+    /// Sources qualifying names in the expression (e.g. `Pfam` of `Pfam:PF00069`)
+    pub fn sources(&self) -> Vec<&str> {
+        let mut sources = Vec::new();
+        if let ExprData::HasNodes(node) = &self.0 {
+            node.sources(&mut sources);
+        }
+        sources
+    }
+
     // pub fn matches_domains(&self, gene_record: &GeneRecord) -> Result<bool, Box<dyn Error>> {
     //     let tags: Vec<&str> = gene_record
     //         .iter_domains()
@@ -53,6 +122,17 @@ impl Expr {
 #[cfg(test)]
 mod test_expr {
     use super::*;
+
+    /// A domain at `start..=end` of a protein of unknown length
+    fn hit(name: &str, start: i32, end: i32) -> Hit<'_> {
+        Hit {
+            name,
+            start,
+            end,
+            ..Default::default()
+        }
+    }
+
     #[test]
     fn or_alias() {
         assert_eq!(
@@ -83,6 +163,373 @@ mod test_expr {
         assert!(expr.matches(&["a", "a", "b"]).unwrap());
     }
 
+    #[test]
+    fn test_count_comparison() {
+        let tags = ["a", "a", "a", "b"];
+        assert!(Expr::from_string("a$>=3").unwrap().matches(&tags).unwrap());
+        assert!(!Expr::from_string("a$>3").unwrap().matches(&tags).unwrap());
+        assert!(Expr::from_string("a$<=3").unwrap().matches(&tags).unwrap());
+        assert!(Expr::from_string("b$<2").unwrap().matches(&tags).unwrap());
+        assert!(Expr::from_string("a$2..4 & b$1")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(!Expr::from_string("a$4..6").unwrap().matches(&tags).unwrap());
+        assert!(Expr::from_string("c$0").unwrap().matches(&tags).unwrap());
+        assert!(!Expr::from_string("!a$>=2").unwrap().matches(&tags).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_count() {
+        assert!(Expr::from_string("a$").is_err());
+        assert!(Expr::from_string("a$>=x & b").is_err());
+        assert!(Expr::from_string("a$6..3").is_err());
+        assert!(Expr::from_string("!a$2$3").is_err());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let tags = [
+            "PTHR47108:SF1",
+            "PTHR47108:SF2",
+            "PF00069",
+            "G3DSA:3.40.50.300",
+        ];
+        assert!(Expr::from_string("PTHR47108*$2")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(Expr::from_string("G3DSA:3.40.50.*")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(Expr::from_string("PF0006?")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(!Expr::from_string("PF0007*")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(Expr::from_string("/^PF0006[0-9]$/ & !/^pthr/")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(Expr::from_string("/^pthr/i$>=2")
+            .unwrap()
+            .matches(&tags)
+            .unwrap());
+        assert!(Expr::from_string("/(a|b/").is_err());
+
+        let expr = Expr::from_string("PTHR47108* | /^PF0006[0-9]$/ | /^PF0?1/ | a").unwrap();
+        let prefixes: Vec<&str> = expr.patterns().iter().map(|p| p.literal_prefix()).collect();
+        assert_eq!(prefixes, vec!["PTHR47108", "PF0006", "PF"]);
+        assert_eq!(expr.names(), vec!["a"]);
+    }
+
+    #[test]
+    fn test_source() {
+        let hit = |name, source| Hit {
+            name,
+            source: Some(source),
+            ..Default::default()
+        };
+        let hits = [
+            hit("PF00069", "Pfam"),
+            hit("GO:0004672", "GoTerm"),
+            hit("G3DSA:3.30.200.20", "Gene3D"),
+        ];
+
+        let expr = Expr::from_string("Pfam:PF00069 & GoTerm:GO:0004672").unwrap();
+        assert!(expr.matches_hits(&hits).unwrap());
+        assert_eq!(expr.sources(), vec!["Pfam", "GoTerm"]);
+        assert!(expr.names().is_empty());
+
+        assert!(Expr::from_string("Gene3D:*")
+            .unwrap()
+            .matches_hits(&hits)
+            .unwrap());
+        assert!(!Expr::from_string("SMART:PF00069")
+            .unwrap()
+            .matches_hits(&hits)
+            .unwrap());
+        assert!(Expr::from_string("Pfam:/^pf/i$1")
+            .unwrap()
+            .matches_hits(&hits)
+            .unwrap());
+
+        // GO and CATH IDs are not qualified
+        let expr = Expr::from_string("GO:0004672 & G3DSA:3.30.200.20").unwrap();
+        assert!(expr.matches_hits(&hits).unwrap());
+        assert_eq!(expr.names(), vec!["GO:0004672", "G3DSA:3.30.200.20"]);
+        // sources without a `Term` variant qualify names only when registered
+        let hits = [hit("NF000001", "NCBIfam")];
+        let expr = Expr::from_string("NCBIfam:NF000001").unwrap();
+        assert!(!expr.matches_hits(&hits).unwrap());
+        assert_eq!(expr.names(), vec!["NCBIfam:NF000001"]);
+        let expr = Expr::from_string_with_sources("NCBIfam:NF0* & !Pfam:*", &["NCBIfam"]).unwrap();
+        assert!(expr.matches_hits(&hits).unwrap());
+        assert_eq!(expr.sources(), vec!["NCBIfam", "Pfam"]);
+    }
+
+    #[test]
+    fn test_desc() {
+        let hit = |name, desc| Hit {
+            name,
+            desc: Some(desc),
+            ..Default::default()
+        };
+        let hits = [
+            hit("PF00069", "Protein kinase domain"),
+            hit("PF00096", "Zinc finger, C2H2 type"),
+            hit("PF00096", "Zinc finger, C2H2 type"),
+            Hit::new("mobidb-lite"),
+        ];
+
+        let expr = Expr::from_string("desc~\"protein kinase\" & PF00096").unwrap();
+        assert!(expr.matches_hits(&hits).unwrap());
+        assert!(expr.needs_all_sources());
+        assert!(Expr::from_string("desc~/zinc finger.*C2H2/i$2")
+            .unwrap()
+            .matches_hits(&hits)
+            .unwrap());
+        assert!(!Expr::from_string("desc~/zinc finger/")
+            .unwrap()
+            .matches_hits(&hits)
+            .unwrap());
+        assert!(!Expr::from_string("Pfam:desc~\"kinase\"")
+            .unwrap()
+            .needs_all_sources());
+        assert!(Expr::from_string("desc~kinase").is_err());
+        assert!(Expr::from_string("desc~\"kinase\"x").is_err());
+    }
+
+    #[test]
+    fn test_order() {
+        // given out of order, evaluated in the order of start
+        let hits = [
+            hit("PF00169", 300, 400),
+            hit("PF00069", 10, 250),
+            hit("PF00400", 500, 540),
+            hit("PF00400", 550, 590),
+        ];
+
+        let matches = |s: &str| Expr::from_string(s).unwrap().matches_hits(&hits).unwrap();
+        assert!(matches("PF00069 -> PF00169"));
+        assert!(!matches("PF00169 -> PF00069"));
+        assert!(matches("PF00069 -> PF00169 -> PF00400 -> PF00400"));
+        assert!(!matches("PF00400 -> PF00400 -> PF00400"));
+        assert!(matches("(PF00001 | PF00069) -> PF00400 & PF00169"));
+        // `!` inverts a single step on either side
+        assert!(matches("PF00069 -> !PF00169"));
+        assert!(!matches("!PF00069 -> PF00169"));
+        assert!(matches("!PF00069 -> PF00400"));
+        assert!(!matches("!(PF00069 -> PF00400)"));
+        assert!(matches("!(PF00400 -> PF00069)"));
+        assert!(matches("PF00069 -> PF*"));
+        for (s, bracketed) in [
+            ("!a -> b", "(!a) -> b"),
+            ("!(a) -> b", "(!a) -> b"),
+            ("!(a | c) -> b", "(!(a | c)) -> b"),
+            ("!(a & c) | b", "(!(a & c)) | b"),
+        ] {
+            assert_eq!(
+                Expr::from_string(s).unwrap(),
+                Expr::from_string(bracketed).unwrap(),
+                "{}",
+                s
+            );
+        }
+
+        // inverted steps match domains of any source, e.g. cross references
+        assert!(Expr::from_string("a -> !b").unwrap().needs_all_sources());
+        assert!(Expr::from_string("!a -> b").unwrap().needs_all_sources());
+        assert!(Expr::from_string("a -> (b | !c)")
+            .unwrap()
+            .needs_all_sources());
+        assert!(!Expr::from_string("a -> b").unwrap().needs_all_sources());
+        assert!(!Expr::from_string("!(a -> b)").unwrap().needs_all_sources());
+
+        // domains at the same position, e.g. a signature and its cross reference, aren't ordered
+        let hits = [
+            hit("PF00069", 10, 250),
+            hit("IPR000719", 10, 250),
+            hit("PF00169", 300, 400),
+        ];
+        let matches = |s: &str| Expr::from_string(s).unwrap().matches_hits(&hits).unwrap();
+        assert!(!matches("PF00069 -> IPR000719"));
+        assert!(!matches("IPR000719 -> PF00069"));
+        assert!(matches("IPR000719 -> PF00169"));
+        assert!(!matches("PF00069 -> IPR000719 -> PF00169"));
+
+        assert!(Expr::from_string("a$2 -> b").is_err());
+        assert!(Expr::from_string("a -> b$>=2").is_err());
+        assert!(Expr::from_string("a ->").is_err());
+        assert_eq!(
+            Expr::from_string("a -> b | c").unwrap().names(),
+            vec!["a", "b", "c"]
+        );
+    }
+
+    #[test]
+    fn test_spatial() {
+        let hits = [
+            hit("PF00069", 10, 250),
+            hit("PF00169", 300, 400),
+            hit("G3DSA:1.10.510.10", 5, 260),
+            hit("PF00400", 380, 420),
+        ];
+
+        let matches = |s: &str| Expr::from_string(s).unwrap().matches_hits(&hits).unwrap();
+        assert!(matches("PF00069 <49> PF00169"));
+        assert!(!matches("PF00069 <48> PF00169"));
+        assert!(matches("PF00169 <49> PF00069"));
+        assert!(matches("PF00169 <overlaps> PF00400"));
+        assert!(!matches("PF00069 <overlaps> PF00169"));
+        assert!(matches("PF00069 <in> G3DSA:*"));
+        assert!(!matches("G3DSA:* <in> PF00069"));
+        assert!(matches("PF00069 <in> G3DSA:* & !(PF00169 <in> PF*)"));
+        assert!(!matches("PF00400 <overlaps> PF00400"));
+        assert!(matches("PF00069<49>PF00169"));
+        assert!(matches("PF00069<in>G3DSA:*"));
+        assert!(!matches("G3DSA:*<in>PF00069"));
+
+        // inverted operands match domains of any source, e.g. cross references
+        assert!(Expr::from_string("a <in> !b").unwrap().needs_all_sources());
+        assert!(Expr::from_string("!a <50> b").unwrap().needs_all_sources());
+        assert!(!Expr::from_string("a <overlaps> b")
+            .unwrap()
+            .needs_all_sources());
+
+        assert!(Expr::from_string("a$2 <10> b").is_err());
+        assert!(Expr::from_string("a <10> b -> c").is_err());
+    }
+
+    #[test]
+    fn test_location() {
+        let hits = [
+            hit("PF00069", 10, 40),
+            hit("PF00069", 420, 480),
+            hit("PF00169", 50, 450),
+        ]
+        .map(|hit| Hit {
+            length: Some(500),
+            ..hit
+        });
+
+        let matches = |s: &str| Expr::from_string(s).unwrap().matches_hits(&hits).unwrap();
+        assert!(matches("PF00069@nterm(40)"));
+        assert!(!matches("PF00069@nterm(39)"));
+        assert!(matches("PF00069@nterm(8%)"));
+        assert!(matches("PF00069@cterm(81)"));
+        assert!(!matches("PF00069@cterm(80)"));
+        assert!(matches("PF00069@cterm(20%)"));
+        assert!(!matches("PF00069@nterm(40)@cterm(20%)"));
+        assert!(matches("PF00069@nterm(40) & PF00069@cterm(20%)"));
+        assert!(matches("PF00169%cov>=0.8"));
+        assert!(!matches("PF00169%cov>0.802"));
+        assert!(matches("PF00069%cov<0.5$2"));
+        assert!(!matches("PF00069@nterm(40)$2"));
+        assert!(matches("PF*@nterm(40) -> PF00169%cov>=0.8"));
+        assert!(matches("/^PF0006/@nterm(40)"));
+
+        // the length is unknown
+        let hits = [hit("PF00069", 10, 40)];
+        let expr = Expr::from_string("PF00069%cov>=0.1").unwrap();
+        assert!(!expr.matches_hits(&hits).unwrap());
+
+        assert!(Expr::from_string("a@nterm(x)").is_err());
+        assert!(Expr::from_string("a@middle").is_err());
+        assert!(Expr::from_string("a%cov~0.5").is_err());
+        // only finite, non-negative extents and coverages
+        assert!(Expr::from_string("a@nterm(-5)").is_err());
+        assert!(Expr::from_string("a@cterm(-10%)").is_err());
+        assert!(Expr::from_string("a@nterm(inf%)").is_err());
+        assert!(Expr::from_string("a@cterm(NaN%)").is_err());
+        assert!(Expr::from_string("a%cov>=NaN").is_err());
+        assert!(Expr::from_string("a%cov<inf").is_err());
+        assert!(Expr::from_string("a%cov>-0.5").is_err());
+    }
+
+    #[test]
+    fn test_gene() {
+        let gene = Gene {
+            length: Some(350),
+            desc: Some("Hypothetical protein"),
+            org: Some("Athaliana"),
+            ..Default::default()
+        };
+        let hits = [Hit::new("PF00069")];
+
+        let matches = |s: &str| {
+            Expr::from_string(s)
+                .unwrap()
+                .matches_gene(&gene, &hits)
+                .unwrap()
+        };
+        assert!(matches("PF00069 & len<400"));
+        assert!(!matches("PF00069 & len>=400"));
+        assert!(matches("len=350"));
+        assert!(matches("gdesc~\"hypothetical\""));
+        assert!(!matches("gdesc~/^hypothetical/"));
+        assert!(matches("gdesc~/^hypothetical/i & org=Athaliana"));
+        assert!(!matches("org=Osativa | !PF00069"));
+        assert!(matches("!(len>500 | PF00169)"));
+        // a domain name starting with `len`
+        assert!(!matches("length"));
+
+        // genes are unknown
+        assert!(!Expr::from_string("len>0").unwrap().matches(&["a"]).unwrap());
+
+        assert!(Expr::from_string("len>x").is_err());
+        assert!(Expr::from_string("org=").is_err());
+        assert!(Expr::from_string("gdesc~\"a\"$2").is_err());
+        assert!(Expr::from_string("len>500 -> PF00069").is_err());
+    }
+
+    #[test]
+    fn test_motif() {
+        let gene = Gene {
+            seq: Some("MAKCPECGKSFSQSSNLQKHQRTHTGEKPY"),
+            ..Default::default()
+        };
+        let hits = [Hit::new("PF00096")];
+
+        let matches = |s: &str| {
+            Expr::from_string(s)
+                .unwrap()
+                .matches_gene(&gene, &hits)
+                .unwrap()
+        };
+        assert!(matches("PF00096 & seq~/C..C.{12}H...H/"));
+        assert!(matches("seq~/c..c/"));
+        assert!(!matches("seq~/C..C.{13}H...H/"));
+        assert!(matches("PF00096 & seq~\"C-x(2)-C-x(12)-H-x(3)-H\""));
+        assert!(matches("(seq~C-x(2)-C-x(12)-H-x(3)-H)"));
+        assert!(matches("seq~<M-[AG]-K-{P}-P."));
+        assert!(!matches("seq~<A-K"));
+        assert!(matches("seq~E-K-P-Y>"));
+        assert!(matches("!seq~W"));
+
+        let expr = Expr::from_string("seq~C-x(2,4)-C & !seq~/W/").unwrap();
+        let motifs = expr.motifs();
+        assert_eq!(motifs.len(), 2);
+        assert_eq!(motifs[0].positions(gene.seq.unwrap()), vec![(4, 7)]);
+
+        assert!(Expr::from_string("seq~C-x(2-C").is_err());
+        assert!(Expr::from_string("seq~C-y-C").is_err());
+        assert!(Expr::from_string("seq~C-x(a)").is_err());
+        assert!(Expr::from_string("seq~/C..C/ -> PF00096").is_err());
+        assert!(Expr::from_string("Pfam:seq~/C..C/").is_err());
+    }
+
+    #[test]
+    fn test_names() {
+        let expr = Expr::from_string("a$2 & !(b | a)").unwrap();
+        assert_eq!(expr.names(), vec!["a", "b"]);
+        assert!(Expr::from_string("").unwrap().names().is_empty());
+    }
+
     #[test]
     fn simple_inversion() {
         let expr = Expr::from_string("!a & b").unwrap();
@@ -0,0 +1,73 @@
+use std::error::Error;
+
+use super::{ast::ParseError, location::Cmp, motif::Motif, pattern::NamePattern};
+
+/// A gene, as seen by gene-level predicates
+#[derive(Debug, Clone, Default)]
+pub struct Gene<'a> {
+    pub length: Option<i32>,
+    pub desc: Option<&'a str>,
+    pub org: Option<&'a str>,
+    pub seq: Option<&'a str>,
+}
+
+/// A predicate on the gene instead of its domains
+/// (e.g. `len>500`, `gdesc~"hypothetical"`, `org=Athaliana`, `seq~/C..C/`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenePredicate {
+    Length(Cmp, i32),
+    Desc(NamePattern),
+    Org(String),
+    Motif(Motif),
+}
+
+impl GenePredicate {
+    /// `None` if `text` isn't a gene-level predicate
+    pub fn parse(text: &str) -> Result<Option<Self>, Box<dyn Error>> {
+        let invalid = || -> Box<dyn Error> {
+            Box::new(ParseError::new(format!(
+                "invalid gene predicate `{}`, expected len<op><N>, gdesc~\"<text>\", org=<org> or seq~<motif>",
+                text
+            )))
+        };
+
+        let predicate = if let Some(args) = text.strip_prefix("len") {
+            let (cmp, n) = match Cmp::split(args) {
+                Some(split) => split,
+                // a domain name starting with `len`
+                None => return Ok(None),
+            };
+            GenePredicate::Length(cmp, n.parse().map_err(|_| invalid())?)
+        } else if let Some(args) = text.strip_prefix("gdesc~") {
+            let (pattern, rest) = NamePattern::text_or_regex(args)?;
+            if !rest.is_empty() {
+                return Err(invalid());
+            }
+            GenePredicate::Desc(pattern)
+        } else if let Some(org) = text.strip_prefix("org=") {
+            if org.is_empty() {
+                return Err(invalid());
+            }
+            GenePredicate::Org(org.to_string())
+        } else if let Some(args) = text.strip_prefix("seq~") {
+            GenePredicate::Motif(Motif::parse(args)?)
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(predicate))
+    }
+
+    pub fn holds(&self, gene: &Gene) -> bool {
+        match self {
+            GenePredicate::Length(cmp, n) => {
+                matches!(gene.length, Some(length) if cmp.holds(length as f64, *n as f64))
+            }
+            GenePredicate::Desc(pattern) => {
+                matches!(gene.desc, Some(desc) if pattern.is_match(desc))
+            }
+            GenePredicate::Org(org) => gene.org == Some(org.as_str()),
+            GenePredicate::Motif(motif) => matches!(gene.seq, Some(seq) if motif.is_match(seq)),
+        }
+    }
+}
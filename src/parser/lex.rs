@@ -1,4 +1,6 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+
+use super::ast::Relation;
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Eq)]
 pub enum Token {
@@ -7,6 +9,10 @@ pub enum Token {
     Invert,
     And,
     Or,
+    /// `->`
+    Then,
+    /// `<N>`, `<overlaps>` or `<in>`
+    Spatial(Relation),
     Name(String),
 }
 
@@ -27,6 +33,16 @@ impl Token {
 enum ParseState {
     Ready,
     InName,
+    /// inside `/.../`, where operators and whitespaces are part of the pattern
+    InRegex,
+    /// inside `"..."` of e.g. `desc~"protein kinase"`
+    InQuote,
+}
+
+/// `<` after `name` compares instead of starting a relation,
+/// e.g. `a$<2`, `len<100`, `a%cov<0.5` or the anchor of `seq~<M-x(2)-C`
+fn is_comparison(name: &str) -> bool {
+    name.ends_with('$') || name.ends_with("%cov") || name == "len" || name.starts_with("seq~")
 }
 
 pub fn lex(s: &str) -> Result<Vec<Token>> {
@@ -34,12 +50,76 @@ pub fn lex(s: &str) -> Result<Vec<Token>> {
     let mut tokens = vec![];
 
     let mut cur_name = String::new();
+    let mut escaped = false;
+
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        let op_token = match state {
+            ParseState::Ready | ParseState::InName if c == '-' && chars.peek() == Some(&'>') => {
+                chars.next();
+                Some(Token::Then)
+            }
+            ParseState::Ready | ParseState::InName if c == '<' && !is_comparison(&cur_name) => {
+                let mut relation = String::new();
+                let mut closed = false;
+                for c in chars.by_ref() {
+                    if c == '>' {
+                        closed = true;
+                        break;
+                    }
+                    relation.push(c);
+                }
+                if !closed {
+                    return Err(anyhow!(format!("unterminated relation: <{}", relation)));
+                }
 
-    for c in s.chars() {
-        let op_token = Token::op_from_char(c);
+                let relation = Relation::parse(&relation).map_err(|e| anyhow!(e.to_string()))?;
+                Some(Token::Spatial(relation))
+            }
+            _ => Token::op_from_char(c),
+        };
         match state {
+            ParseState::InRegex => {
+                cur_name.push(c);
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '/' {
+                    // a count may follow, e.g. `/^PF00/$>=2`
+                    state = ParseState::InName;
+                }
+            }
+            ParseState::InQuote => {
+                cur_name.push(c);
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    state = ParseState::InName;
+                }
+            }
             ParseState::InName => {
-                if let Some(op) = op_token {
+                if c == '('
+                    && (cur_name.ends_with("@nterm")
+                        || cur_name.ends_with("@cterm")
+                        || cur_name.starts_with("seq~"))
+                {
+                    // arguments of a location or a PROSITE repetition, e.g. `a@nterm(40)`, `seq~C-x(2)-C`
+                    cur_name.push(c);
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        cur_name.push(c);
+                        if c == ')' {
+                            closed = true;
+                            break;
+                        }
+                    }
+                    if !closed {
+                        return Err(anyhow!(format!("unclosed parenthesis: {}", cur_name)));
+                    }
+                } else if let Some(op) = op_token {
                     tokens.push(Token::Name(cur_name.to_owned()));
 
                     tokens.push(op);
@@ -50,6 +130,13 @@ pub fn lex(s: &str) -> Result<Vec<Token>> {
                     tokens.push(Token::Name(cur_name.to_owned()));
                     state = ParseState::Ready;
                     cur_name = String::new();
+                } else if c == '/' && (cur_name.ends_with(':') || cur_name.ends_with('~')) {
+                    // qualified regex or predicate, e.g. `Pfam:/^PF00/`, `desc~/kinase/`
+                    cur_name.push(c);
+                    state = ParseState::InRegex
+                } else if c == '"' && cur_name.ends_with('~') {
+                    cur_name.push(c);
+                    state = ParseState::InQuote
                 } else {
                     cur_name.push(c)
                 }
@@ -57,6 +144,9 @@ pub fn lex(s: &str) -> Result<Vec<Token>> {
             ParseState::Ready => {
                 if let Some(op) = op_token {
                     tokens.push(op);
+                } else if c == '/' {
+                    cur_name.push(c);
+                    state = ParseState::InRegex
                 } else if !c.is_whitespace() {
                     cur_name.push(c);
                     state = ParseState::InName
@@ -65,6 +155,14 @@ pub fn lex(s: &str) -> Result<Vec<Token>> {
         }
     }
 
+    if state == ParseState::InRegex {
+        return Err(anyhow!(format!("unterminated regex: {}", cur_name)));
+    }
+
+    if state == ParseState::InQuote {
+        return Err(anyhow!(format!("unterminated quote: {}", cur_name)));
+    }
+
     if !cur_name.is_empty() {
         tokens.push(Token::Name(cur_name.to_owned()));
     }
@@ -128,4 +226,126 @@ mod test_lex {
             tokens
         )
     }
+
+    #[test]
+    fn test_regex() {
+        let s = "/^PF0006[0-9]|PF01$/$>=2 & !/a b\\/c/";
+        let tokens = lex(s).unwrap();
+        assert_eq!(
+            vec![
+                Token::Name("/^PF0006[0-9]|PF01$/$>=2".to_string()),
+                Token::And,
+                Token::Invert,
+                Token::Name("/a b\\/c/".to_string()),
+            ],
+            tokens
+        );
+
+        assert!(lex("/^PF00 & a").is_err());
+    }
+
+    #[test]
+    fn test_predicate() {
+        let s = "desc~\"protein kinase\" | desc~/zinc finger.*C2H2/i";
+        let tokens = lex(s).unwrap();
+        assert_eq!(
+            vec![
+                Token::Name("desc~\"protein kinase\"".to_string()),
+                Token::Or,
+                Token::Name("desc~/zinc finger.*C2H2/i".to_string()),
+            ],
+            tokens
+        );
+
+        assert!(lex("desc~\"kinase & a").is_err());
+    }
+
+    #[test]
+    fn test_then() {
+        assert_eq!(
+            vec![
+                Token::Name("PF00069".to_string()),
+                Token::Then,
+                Token::Name("R-HSA-1".to_string()),
+                Token::Then,
+                Token::Name("b".to_string()),
+            ],
+            lex("PF00069 -> R-HSA-1->b").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_spatial() {
+        assert_eq!(
+            vec![
+                Token::Name("a$<2".to_string()),
+                Token::Spatial(Relation::Within(50)),
+                Token::Name("b".to_string()),
+                Token::Or,
+                Token::Name("c".to_string()),
+                Token::Spatial(Relation::Inside),
+                Token::Name("d".to_string()),
+            ],
+            lex("a$<2 <50> b | c <in> d").unwrap()
+        );
+
+        // without spaces
+        assert_eq!(
+            vec![
+                Token::Name("a".to_string()),
+                Token::Spatial(Relation::Within(50)),
+                Token::Name("b".to_string()),
+                Token::And,
+                Token::Name("/^PF/".to_string()),
+                Token::Spatial(Relation::Inside),
+                Token::Name("d".to_string()),
+            ],
+            lex("a<50>b & /^PF/<in>d").unwrap()
+        );
+
+        // comparisons in names
+        assert_eq!(
+            vec![
+                Token::Name("len<100".to_string()),
+                Token::And,
+                Token::Name("a%cov<0.5$<=2".to_string()),
+                Token::And,
+                Token::Name("seq~<M-x(2)-C".to_string()),
+            ],
+            lex("len<100 & a%cov<0.5$<=2 & seq~<M-x(2)-C").unwrap()
+        );
+
+        assert!(lex("a <near> b").is_err());
+        assert!(lex("a <50 b").is_err());
+    }
+
+    #[test]
+    fn test_location() {
+        assert_eq!(
+            vec![
+                Token::OpenBracket,
+                Token::Name("a@nterm(40)".to_string()),
+                Token::And,
+                Token::Name("b@cterm(20%)%cov>=0.5".to_string()),
+                Token::CloseBracket,
+            ],
+            lex("(a@nterm(40) & b@cterm(20%)%cov>=0.5)").unwrap()
+        );
+
+        assert!(lex("a@nterm(40").is_err());
+    }
+
+    #[test]
+    fn test_motif() {
+        assert_eq!(
+            vec![
+                Token::OpenBracket,
+                Token::Name("seq~C-x(2,4)-C".to_string()),
+                Token::Or,
+                Token::Name("seq~/C.{2}C/".to_string()),
+                Token::CloseBracket,
+            ],
+            lex("(seq~C-x(2,4)-C | seq~/C.{2}C/)").unwrap()
+        );
+    }
 }
@@ -0,0 +1,156 @@
+use std::error::Error;
+
+use super::ast::{Count, Hit, ParseError};
+
+/// Part of a protein from one of its ends
+#[derive(Debug, Clone, PartialEq)]
+pub enum Extent {
+    /// `40`
+    Residues(i32),
+    /// `20%`
+    Fraction(f64),
+}
+
+impl Extent {
+    /// `None` unless a non-negative number of residues or a finite non-negative percentage
+    fn parse(s: &str) -> Option<Self> {
+        let extent = match s.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent.trim().parse::<f64>().ok()?;
+                if !percent.is_finite() || percent < 0.0 {
+                    return None;
+                }
+                Extent::Fraction(percent / 100.0)
+            }
+            None => Extent::Residues(s.trim().parse().ok().filter(|n| *n >= 0)?),
+        };
+
+        Some(extent)
+    }
+
+    fn residues(&self, length: Option<i32>) -> Option<f64> {
+        match *self {
+            Extent::Residues(n) => Some(n as f64),
+            Extent::Fraction(f) => Some(length? as f64 * f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Cmp {
+    /// Split a leading `>=`, `<=`, `>`, `<` or `=` from `s`
+    pub fn split(s: &str) -> Option<(Self, &str)> {
+        [
+            (">=", Cmp::Ge),
+            ("<=", Cmp::Le),
+            (">", Cmp::Gt),
+            ("<", Cmp::Lt),
+            ("=", Cmp::Eq),
+        ]
+        .into_iter()
+        .find_map(|(op, cmp)| Some((cmp, s.strip_prefix(op)?)))
+    }
+
+    pub fn holds(&self, lhs: f64, rhs: f64) -> bool {
+        match self {
+            Cmp::Lt => lhs < rhs,
+            Cmp::Le => lhs <= rhs,
+            Cmp::Gt => lhs > rhs,
+            Cmp::Ge => lhs >= rhs,
+            Cmp::Eq => (lhs - rhs).abs() < f64::EPSILON,
+        }
+    }
+}
+
+/// Where a domain lies in the protein, written after the name
+/// (e.g. `PF00001@nterm(40)`, `PF00001@cterm(20%)`, `PF00001%cov>=0.7`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Location {
+    /// the domain ends within the extent from the N-terminus
+    NTerm(Extent),
+    /// the domain starts within the extent from the C-terminus
+    CTerm(Extent),
+    /// the fraction of the protein covered by the domain
+    Coverage(Cmp, f64),
+}
+
+// `Location` holds f64, but `parse_suffix` only accepts finite values, so NaN never appears
+impl Eq for Location {}
+
+impl Location {
+    pub fn holds(&self, hit: &Hit) -> bool {
+        match self {
+            Location::NTerm(extent) => {
+                matches!(extent.residues(hit.length), Some(n) if hit.end as f64 <= n)
+            }
+            Location::CTerm(extent) => match (hit.length, extent.residues(hit.length)) {
+                (Some(length), Some(n)) => (hit.start - 1) as f64 >= length as f64 - n,
+                _ => false,
+            },
+            Location::Coverage(cmp, value) => match hit.length {
+                Some(length) if length > 0 => {
+                    let coverage = (hit.end - hit.start + 1) as f64 / length as f64;
+                    cmp.holds(coverage, *value)
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+fn invalid(s: &str) -> Box<dyn Error> {
+    Box::new(ParseError::new(format!(
+        "unexpected `{}`, expected @nterm(N), @cterm(N), %cov>=X or $<count>",
+        s
+    )))
+}
+
+/// Parse what follows a name or a pattern: locations and an optional `$<count>` at the end
+pub fn parse_suffix(s: &str) -> Result<(Vec<Location>, Option<Count>), Box<dyn Error>> {
+    let mut locations = Vec::new();
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        if let Some(count) = rest.strip_prefix('$') {
+            return Ok((locations, Some(Count::parse(count)?)));
+        }
+
+        let (location, next) = if let Some(args) = rest
+            .strip_prefix("@nterm(")
+            .or_else(|| rest.strip_prefix("@cterm("))
+        {
+            let end = args.find(')').ok_or_else(|| invalid(rest))?;
+            let extent = Extent::parse(&args[..end]).ok_or_else(|| invalid(rest))?;
+            let location = if rest.starts_with("@nterm") {
+                Location::NTerm(extent)
+            } else {
+                Location::CTerm(extent)
+            };
+            (location, &args[end + 1..])
+        } else if let Some(cov) = rest.strip_prefix("%cov") {
+            let (cmp, args) = Cmp::split(cov).ok_or_else(|| invalid(rest))?;
+            let end = args.find(['@', '%', '$']).unwrap_or(args.len());
+            let value = args[..end]
+                .parse::<f64>()
+                .ok()
+                .filter(|v| v.is_finite() && *v >= 0.0)
+                .ok_or_else(|| invalid(rest))?;
+            (Location::Coverage(cmp, value), &args[end..])
+        } else {
+            return Err(invalid(rest));
+        };
+
+        locations.push(location);
+        rest = next;
+    }
+
+    Ok((locations, None))
+}
@@ -4,6 +4,12 @@ This parser is referred to https://github.com/Smittyvb/ttw/blob/f77fa34e62739b02
 
 pub mod ast;
 pub mod expr;
+pub mod gene;
 pub mod lex;
+pub mod location;
+pub mod motif;
+pub mod pattern;
 
+pub use ast::Hit;
 pub use expr::{Expr, MAX_RECURSION};
+pub use gene::Gene;
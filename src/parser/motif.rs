@@ -0,0 +1,164 @@
+use std::{error::Error, fmt};
+
+use regex::{Regex, RegexBuilder};
+
+use super::ast::ParseError;
+
+/// A sequence motif, written as a regex (`seq~/C..C.{12}H...H/`)
+/// or a PROSITE pattern (`seq~"C-x(2)-C-x(12)-H-x(3)-H"`)
+#[derive(Clone)]
+pub struct Motif {
+    text: String,
+    regex: Regex,
+}
+
+impl PartialEq for Motif {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Motif {}
+
+impl fmt::Debug for Motif {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Motif({})", self.text)
+    }
+}
+
+fn invalid(text: &str, reason: &str) -> Box<dyn Error> {
+    Box::new(ParseError::new(format!(
+        "invalid motif `{}`: {}",
+        text, reason
+    )))
+}
+
+impl Motif {
+    /// `/<regex>/`, `"<prosite>"` or a PROSITE pattern without parentheses
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        if text.starts_with('/') {
+            Self::regex(text)
+        } else {
+            let pattern = match text.strip_prefix('"') {
+                Some(quoted) => quoted
+                    .strip_suffix('"')
+                    .ok_or_else(|| invalid(text, "unterminated quote"))?,
+                None => text,
+            };
+            Self::prosite(pattern)
+        }
+    }
+
+    /// `/<regex>/`, matched case-insensitively as sequences can be lowercase
+    pub fn regex(text: &str) -> Result<Self, Box<dyn Error>> {
+        let pattern = text
+            .strip_prefix('/')
+            .and_then(|s| s.strip_suffix('/'))
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| invalid(text, "expected /<regex>/"))?;
+
+        let regex = RegexBuilder::new(&pattern.replace("\\/", "/"))
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| invalid(text, &e.to_string()))?;
+
+        Ok(Self {
+            text: text.to_string(),
+            regex,
+        })
+    }
+
+    /// A PROSITE pattern, e.g. `<M-x(2)-[ST]-{P}-C-x(2,4)-H>.`
+    pub fn prosite(text: &str) -> Result<Self, Box<dyn Error>> {
+        let body = text.strip_suffix('.').unwrap_or(text);
+        if body.is_empty() {
+            return Err(invalid(text, "empty pattern"));
+        }
+
+        let mut pattern = String::new();
+        for element in body.split('-') {
+            let element = match element.strip_prefix('<') {
+                Some(element) => {
+                    pattern.push('^');
+                    element
+                }
+                None => element,
+            };
+            let (element, anchored) = match element.strip_suffix('>') {
+                Some(element) => (element, true),
+                None => (element, false),
+            };
+
+            let (residue, repeat) = match element.split_once('(') {
+                Some((residue, repeat)) => (
+                    residue,
+                    Some(
+                        repeat
+                            .strip_suffix(')')
+                            .ok_or_else(|| invalid(text, "unclosed repetition"))?,
+                    ),
+                ),
+                None => (element, None),
+            };
+
+            let is_residues = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_uppercase());
+            if residue == "x" {
+                pattern.push('.');
+            } else if let Some(set) = residue.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+                if !is_residues(set) {
+                    return Err(invalid(text, &format!("unexpected `{}`", residue)));
+                }
+                pattern.push_str(&format!("[{}]", set));
+            } else if let Some(set) = residue.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                if !is_residues(set) {
+                    return Err(invalid(text, &format!("unexpected `{}`", residue)));
+                }
+                pattern.push_str(&format!("[^{}]", set));
+            } else if residue.len() == 1 && is_residues(residue) {
+                pattern.push_str(residue);
+            } else {
+                return Err(invalid(text, &format!("unexpected `{}`", residue)));
+            }
+
+            if let Some(repeat) = repeat {
+                let valid = repeat
+                    .split(',')
+                    .all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()));
+                if !valid || repeat.split(',').count() > 2 {
+                    return Err(invalid(text, &format!("unexpected `({})`", repeat)));
+                }
+                pattern.push_str(&format!("{{{}}}", repeat));
+            }
+
+            if anchored {
+                pattern.push('$');
+            }
+        }
+
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| invalid(text, &e.to_string()))?;
+
+        Ok(Self {
+            text: text.to_string(),
+            regex,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_match(&self, seq: &str) -> bool {
+        self.regex.is_match(seq)
+    }
+
+    /// 1-based inclusive positions of the non-overlapping matches in `seq`
+    pub fn positions(&self, seq: &str) -> Vec<(usize, usize)> {
+        self.regex
+            .find_iter(seq)
+            .map(|m| (m.start() + 1, m.end()))
+            .collect()
+    }
+}
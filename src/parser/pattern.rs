@@ -0,0 +1,189 @@
+use std::{error::Error, fmt};
+
+use regex::{Regex, RegexBuilder};
+
+use super::ast::ParseError;
+
+/// A glob (`PTHR47108*`) or regex (`/^PF0006[0-9]$/`) matching domain names
+#[derive(Clone)]
+pub struct NamePattern {
+    text: String,
+    regex: Regex,
+    prefix: String,
+}
+
+impl PartialEq for NamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for NamePattern {}
+
+impl fmt::Debug for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "NamePattern({})", self.text)
+    }
+}
+
+impl NamePattern {
+    pub fn is_glob(text: &str) -> bool {
+        text.contains(['*', '?'])
+    }
+
+    /// `*` matches any characters and `?` matches a single character
+    pub fn glob(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut pattern = String::from("^");
+        for c in text.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        pattern.push('$');
+
+        let prefix = text
+            .chars()
+            .take_while(|c| !matches!(c, '*' | '?'))
+            .collect();
+
+        Ok(Self {
+            text: text.to_string(),
+            regex: Regex::new(&pattern)?,
+            prefix,
+        })
+    }
+
+    /// Parse `/<regex>/<flags>` and return the rest of `text` (e.g. a count).
+    /// The only flag is `i` (case insensitive).
+    pub fn regex(text: &str) -> Result<(Self, &str), Box<dyn Error>> {
+        let end = match text.rfind('/') {
+            Some(end) if text.starts_with('/') && end > 0 => end,
+            _ => {
+                return Err(Box::new(ParseError::new(format!(
+                    "invalid regex `{}`, expected /<regex>/",
+                    text
+                ))))
+            }
+        };
+
+        let (pattern, rest) = (&text[1..end], &text[end + 1..]);
+        let (flags, rest) = rest.split_at(
+            rest.find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(rest.len()),
+        );
+
+        let mut builder = RegexBuilder::new(&pattern.replace("\\/", "/"));
+        for flag in flags.chars() {
+            match flag {
+                'i' => builder.case_insensitive(true),
+                _ => {
+                    return Err(Box::new(ParseError::new(format!(
+                        "unknown regex flag `{}` in `{}`",
+                        flag, text
+                    ))))
+                }
+            };
+        }
+        let regex = builder
+            .build()
+            .map_err(|e| ParseError::new(format!("invalid regex `{}`: {}", text, e)))?;
+
+        let prefix = if flags.is_empty() {
+            literal_prefix(pattern)
+        } else {
+            String::new()
+        };
+
+        Ok((
+            Self {
+                text: text[..end + 1 + flags.len()].to_string(),
+                regex,
+                prefix,
+            },
+            rest,
+        ))
+    }
+
+    /// Parse `"<text>"`, matching names containing `text` case-insensitively,
+    /// and return the rest of `text` (e.g. a count)
+    pub fn quoted(text: &str) -> Result<(Self, &str), Box<dyn Error>> {
+        let mut escaped = false;
+        let end = text.char_indices().skip(1).find_map(|(i, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => return Some(i),
+                _ => {}
+            }
+            None
+        });
+        let end = match end {
+            Some(end) if text.starts_with('"') => end,
+            _ => {
+                return Err(Box::new(ParseError::new(format!(
+                    "invalid text `{}`, expected \"<text>\"",
+                    text
+                ))))
+            }
+        };
+
+        let literal = text[1..end].replace("\\\"", "\"");
+        let regex = RegexBuilder::new(&regex::escape(&literal))
+            .case_insensitive(true)
+            .build()?;
+
+        Ok((
+            Self {
+                text: text[..end + 1].to_string(),
+                regex,
+                prefix: String::new(),
+            },
+            &text[end + 1..],
+        ))
+    }
+
+    /// `"<text>"` or `/<regex>/<flags>`, followed by the rest of `text`
+    pub fn text_or_regex(text: &str) -> Result<(Self, &str), Box<dyn Error>> {
+        if text.starts_with('"') {
+            Self::quoted(text)
+        } else {
+            Self::regex(text)
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+
+    /// Characters every matched name starts with, used to infer sources.
+    /// Can be empty.
+    pub fn literal_prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+/// Literal characters after `^` of a regex, e.g. `PF0006` of `^PF0006[0-9]$`
+fn literal_prefix(pattern: &str) -> String {
+    let body = match pattern.strip_prefix('^') {
+        Some(body) if !body.contains('|') => body,
+        _ => return String::new(),
+    };
+
+    let mut prefix: String = body
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '-'))
+        .collect();
+
+    // the last character is optional in e.g. `^PF0?`
+    if matches!(body[prefix.len()..].chars().next(), Some('?' | '*' | '{')) {
+        prefix.pop();
+    }
+
+    prefix
+}
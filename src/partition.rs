@@ -1,7 +1,11 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use arrow2::{
+    datatypes::{DataType as ArrowDataType, Schema},
+    io::ipc::read::read_file_metadata,
+};
 use polars::{
     io::SerReader,
-    prelude::{DataFrame, IpcReader},
+    prelude::{DataFrame, DataType, IpcReader, Series},
 };
 use polars_core::{utils::accumulate_dataframes_vertical, POOL};
 use std::{
@@ -13,12 +17,14 @@ use std::{
 
 use rayon::prelude::*;
 
-use crate::records::Term;
+use crate::records::{Term, SCHEMA_VERSION, SCHEMA_VERSION_KEY};
 
 pub struct PartitionedIpcReader {
     dir: PathBuf,
     org: Option<Vec<String>>,
     source: Option<Vec<String>>,
+    schema: Option<Schema>,
+    partition_columns: Vec<String>,
 }
 
 fn check_path(path: &Path, map: &HashMap<String, Vec<String>>) -> bool {
@@ -46,6 +52,76 @@ fn check_path(path: &Path, map: &HashMap<String, Vec<String>>) -> bool {
     flags.into_iter().all(|b| b)
 }
 
+fn check_schema_version(path: &Path) -> Result<()> {
+    let metadata = read_file_metadata(&mut BufReader::new(File::open(path)?))?;
+    let version: u32 = match metadata.schema.metadata.get(SCHEMA_VERSION_KEY) {
+        Some(v) => v.parse()?,
+        None => 1,
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(format!(
+            "{} is written with schema version {}, but this ipsr supports up to {}",
+            path.display(),
+            version,
+            SCHEMA_VERSION
+        )));
+    }
+
+    Ok(())
+}
+
+fn to_polars_dtype(data_type: &ArrowDataType) -> Result<DataType> {
+    let dtype = match data_type {
+        ArrowDataType::Int16 => DataType::Int16,
+        ArrowDataType::Int32 => DataType::Int32,
+        ArrowDataType::Int64 => DataType::Int64,
+        ArrowDataType::Float64 => DataType::Float64,
+        ArrowDataType::Utf8 => DataType::Utf8,
+        ArrowDataType::List(field) | ArrowDataType::LargeList(field) => {
+            DataType::List(Box::new(to_polars_dtype(&field.data_type)?))
+        }
+        _ => return Err(anyhow!(format!("Unsupported data type: {:?}", data_type))),
+    };
+
+    Ok(dtype)
+}
+
+/// Upcast columns to the types of `schema` and fill columns missing in `df` with nulls
+fn conform(df: DataFrame, schema: &Schema) -> Result<DataFrame> {
+    let height = df.height();
+
+    let columns = schema
+        .fields
+        .iter()
+        .map(|field| {
+            let dtype = to_polars_dtype(&field.data_type)?;
+            let series = match df.column(&field.name) {
+                Ok(s) if s.dtype() == &dtype => s.clone(),
+                Ok(s) => s.cast(&dtype)?,
+                Err(_) => Series::full_null(&field.name, height, &dtype),
+            };
+            Ok(series)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(DataFrame::new(columns)?)
+}
+
+fn add_partition_columns(df: &mut DataFrame, path: &Path, columns: &[String]) -> Result<()> {
+    let height = df.height();
+
+    for p in path.iter() {
+        if let Some((k, v)) = p.to_str().and_then(|p| p.split_once('=')) {
+            if columns.iter().any(|c| c == k) {
+                df.with_column(Series::new(k, vec![v; height]))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn select_paths(glob: &str, map: &HashMap<String, Vec<String>>) -> Result<Vec<PathBuf>> {
     let mut ret = vec![];
 
@@ -65,9 +141,24 @@ impl PartitionedIpcReader {
             dir,
             org: None,
             source: None,
+            schema: None,
+            partition_columns: Vec::new(),
         }
     }
 
+    /// Conform every partition to `schema`, so partitions written by older versions
+    /// (Int16 coordinates, fewer columns) can be read together with new ones
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Add the values of partition keys (e.g. `source` of `source=Pfam`) as Utf8 columns
+    pub fn with_partition_columns(mut self, columns: &[&str]) -> Self {
+        self.partition_columns = columns.iter().map(|c| c.to_string()).collect();
+        self
+    }
+
     pub fn with_org(mut self, org: Option<Vec<String>>) -> Self {
         self.org = org;
         self
@@ -92,16 +183,51 @@ impl PartitionedIpcReader {
         map
     }
 
+    /// Values of the partition key `key` in the selected files (e.g. `Pfam` of `source=Pfam`)
+    pub fn partition_values(&self, key: &str) -> Result<Vec<String>> {
+        let paths = select_paths(&format!("{}/**/*.ipc", self.dir.display()), &self.map())?;
+
+        let mut values: Vec<String> = Vec::new();
+        for path in paths.iter() {
+            for p in path.iter() {
+                if let Some((k, v)) = p.to_str().and_then(|p| p.split_once('=')) {
+                    if k == key && !values.iter().any(|x| x == v) {
+                        values.push(v.to_string());
+                    }
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
     pub fn finish(&self) -> Result<DataFrame> {
         let map = self.map();
 
         let paths = select_paths(&format!("{}/**/*.ipc", self.dir.display()), &map)?;
 
+        if paths.is_empty() {
+            let mut df = match &self.schema {
+                Some(schema) => conform(DataFrame::default(), schema)?,
+                None => DataFrame::default(),
+            };
+            for column in self.partition_columns.iter() {
+                df.with_column(Series::new_empty(column, &DataType::Utf8))?;
+            }
+            return Ok(df);
+        }
+
         let parsed_dfs = POOL.install(|| {
             paths
                 .into_par_iter()
                 .map(|path| {
-                    let df = IpcReader::new(BufReader::new(File::open(path)?)).finish()?;
+                    check_schema_version(&path)?;
+                    let df = IpcReader::new(BufReader::new(File::open(&path)?)).finish()?;
+                    let mut df = match &self.schema {
+                        Some(schema) => conform(df, schema)?,
+                        None => df,
+                    };
+                    add_partition_columns(&mut df, &path, &self.partition_columns)?;
                     Ok(df)
                 })
                 .collect::<Result<Vec<_>>>()
@@ -111,3 +237,171 @@ impl PartitionedIpcReader {
         Ok(df)
     }
 }
+
+#[cfg(test)]
+mod test_partition {
+    use super::*;
+    use std::{fs, io::BufWriter, sync::Arc};
+
+    use arrow2::{
+        array::{ArrayRef, Int16Array, Utf8Array},
+        chunk::Chunk,
+        datatypes::{Field, Metadata},
+        io::ipc::write::{FileWriter, WriteOptions},
+    };
+
+    use crate::records::{domain_record_schema, Domain, DomainRecords};
+
+    /// The domain table before the schema version was written, with Int16 coordinates
+    fn v1_schema() -> Schema {
+        Schema::from(vec![
+            Field::new("start", ArrowDataType::Int16, false),
+            Field::new("end", ArrowDataType::Int16, false),
+            Field::new("domain_name", ArrowDataType::Utf8, false),
+            Field::new("domain_desc", ArrowDataType::Utf8, true),
+            Field::new("gene_id", ArrowDataType::Utf8, false),
+        ])
+    }
+
+    fn write_v1(path: &Path, schema: &Schema) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let chunk = Chunk::try_new(vec![
+            Arc::new(Int16Array::from_slice(&[1, 300])) as ArrayRef,
+            Arc::new(Int16Array::from_slice(&[250, 420])) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from_slice(&["PF00069", "PF00169"])) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&[
+                Some("Protein kinase domain"),
+                None,
+            ])) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from_slice(&["g1", "g1"])) as ArrayRef,
+        ])
+        .unwrap();
+
+        let mut writer = FileWriter::try_new(
+            BufWriter::new(File::create(path).unwrap()),
+            schema,
+            None,
+            WriteOptions { compression: None },
+        )
+        .unwrap();
+        writer.write(&chunk, None).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        write_v1(
+            &dir.path().join("org=A").join("source=Pfam").join("v1.ipc"),
+            &v1_schema(),
+        );
+
+        // a file of the current version next to it
+        let mut domain_records = DomainRecords::new(10, dir.path().join("org=A"));
+        domain_records
+            .push(
+                Term::Gene3D,
+                Domain {
+                    evalue: Some(1e-10),
+                    ..Domain::new(
+                        5,
+                        260,
+                        "G3DSA:1.10.510.10".to_string(),
+                        None,
+                        "g1".to_string(),
+                    )
+                },
+            )
+            .unwrap();
+        domain_records.finish().unwrap();
+
+        let df = PartitionedIpcReader::new(dir.path().to_path_buf())
+            .with_schema(domain_record_schema())
+            .with_partition_columns(&["source"])
+            .finish()
+            .unwrap();
+
+        let mut columns: Vec<&str> = domain_record_schema()
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect();
+        columns.push("source");
+        assert_eq!(df.get_column_names(), columns);
+        assert_eq!(df.height(), 3);
+
+        assert_eq!(df["start"].dtype(), &DataType::Int32);
+        assert_eq!(df["end"].dtype(), &DataType::Int32);
+        assert_eq!(df["hmm_start"].dtype(), &DataType::Int32);
+        assert_eq!(df["evalue"].dtype(), &DataType::Float64);
+
+        let sources: Vec<Option<&str>> = df["source"].utf8().unwrap().into_iter().collect();
+        let v1 = sources.iter().position(|s| *s == Some("Pfam")).unwrap();
+        let v2 = sources.iter().position(|s| *s == Some("Gene3D")).unwrap();
+
+        // coordinates are upcast and the columns missing in v1 are null
+        assert_eq!(df["end"].i32().unwrap().get(v1), Some(250));
+        assert_eq!(df["evalue"].f64().unwrap().get(v1), None);
+        assert_eq!(df["score"].f64().unwrap().get(v1), None);
+        assert_eq!(df["status"].utf8().unwrap().get(v1), None);
+        assert_eq!(df["end"].i32().unwrap().get(v2), Some(260));
+        assert_eq!(df["evalue"].f64().unwrap().get(v2), Some(1e-10));
+
+        let mut ends: Vec<Option<i32>> = df["end"].i32().unwrap().into_iter().collect();
+        ends.sort();
+        assert_eq!(ends, vec![Some(250), Some(260), Some(420)]);
+    }
+
+    #[test]
+    fn test_future_schema_version() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut metadata = Metadata::new();
+        metadata.insert(
+            SCHEMA_VERSION_KEY.to_string(),
+            (SCHEMA_VERSION + 1).to_string(),
+        );
+        write_v1(
+            &dir.path()
+                .join("org=A")
+                .join("source=Pfam")
+                .join("future.ipc"),
+            &v1_schema().with_metadata(metadata),
+        );
+
+        let err = PartitionedIpcReader::new(dir.path().to_path_buf())
+            .with_schema(domain_record_schema())
+            .finish()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("schema version {}", SCHEMA_VERSION + 1)));
+    }
+
+    #[test]
+    fn test_partition_values() {
+        let dir = tempfile::tempdir().unwrap();
+        for (org, source) in [("A", "Pfam"), ("A", "NCBIfam"), ("B", "SMART")] {
+            write_v1(
+                &dir.path()
+                    .join(format!("org={}", org))
+                    .join(format!("source={}", source))
+                    .join("v1.ipc"),
+                &v1_schema(),
+            );
+        }
+
+        let reader = PartitionedIpcReader::new(dir.path().to_path_buf());
+        let mut sources = reader.partition_values("source").unwrap();
+        sources.sort();
+        assert_eq!(sources, vec!["NCBIfam", "Pfam", "SMART"]);
+
+        let mut sources = reader
+            .with_org(Some(vec!["A".to_string()]))
+            .partition_values("source")
+            .unwrap();
+        sources.sort();
+        assert_eq!(sources, vec!["NCBIfam", "Pfam"]);
+    }
+}
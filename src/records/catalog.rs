@@ -0,0 +1,275 @@
+use anyhow::Result;
+use arrow2::array::ArrayRef;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::BufWriter,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+};
+
+use arrow2::{
+    array::{Int64Array, Utf8Array},
+    chunk::Chunk,
+    datatypes::{DataType, Field, Schema},
+    io::ipc::write::{self, Compression, FileWriter},
+};
+
+use super::{schema_metadata, Term};
+use crate::{parser::pattern::NamePattern, partition::PartitionedIpcReader};
+
+pub fn catalog_schema() -> Schema {
+    Schema::from(vec![
+        Field::new("domain_name", DataType::Utf8, false),
+        Field::new("source", DataType::Utf8, false),
+        Field::new("count", DataType::Int64, false),
+    ])
+    .with_metadata(schema_metadata())
+}
+
+/// Number of rows of each domain name and source, written to `catalog/org=<org>/<uuid>.ipc`
+/// so `find` can read only the partitions holding the names of an expression.
+/// The counts are held until `write`, one per distinct domain name and source.
+#[derive(Debug, Default)]
+pub struct CatalogRecords {
+    counts: HashMap<Term, HashMap<String, i64>>,
+}
+
+impl CatalogRecords {
+    pub fn push(&mut self, source: &Term, domain_name: &str) {
+        let names = self.counts.entry(source.clone()).or_default();
+
+        match names.get_mut(domain_name) {
+            Some(count) => *count += 1,
+            None => {
+                names.insert(domain_name.to_string(), 1);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut domain_names = Vec::new();
+        let mut sources = Vec::new();
+        let mut counts = Vec::new();
+        for (source, names) in self.counts.iter() {
+            for (domain_name, count) in names.iter() {
+                domain_names.push(domain_name.as_str());
+                sources.push(source.to_string());
+                counts.push(*count);
+            }
+        }
+
+        let chunk = Chunk::try_new(vec![
+            Arc::new(Utf8Array::<i32>::from_slice(&domain_names)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from_slice(&sources)) as ArrayRef,
+            Arc::new(Int64Array::from_slice(&counts)) as ArrayRef,
+        ])?;
+
+        let options = write::WriteOptions {
+            compression: Some(Compression::LZ4),
+        };
+        let mut writer = FileWriter::try_new(
+            BufWriter::new(File::create(path)?),
+            &catalog_schema(),
+            None,
+            options,
+        )?;
+        writer.write(&chunk, None)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Orgs which have a partition directly under `dir`, e.g. `dir/org=<org>`
+fn partitioned_orgs(dir: &Path) -> Result<HashSet<String>> {
+    let mut orgs = HashSet::new();
+    if !dir.exists() {
+        return Ok(orgs);
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if let Some(org) = entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.strip_prefix("org="))
+        {
+            orgs.insert(org.to_string());
+        }
+    }
+
+    Ok(orgs)
+}
+
+/// Sources of each domain name in a database
+pub struct Catalog {
+    sources: HashMap<String, Vec<Term>>,
+}
+
+impl Catalog {
+    /// Read the catalog of `org` (all orgs if `None`) from `dir/catalog`.
+    /// `None` if an org was registered before catalogs were written, since the catalog can't cover it.
+    pub fn read(dir: &Path, org: Option<Vec<String>>) -> Result<Option<Self>> {
+        let registered = partitioned_orgs(&dir.join("domain"))?;
+        let cataloged = partitioned_orgs(&dir.join("catalog"))?;
+
+        let uncovered = registered
+            .iter()
+            .filter(|o| org.as_ref().map_or(true, |org| org.contains(*o)))
+            .any(|o| !cataloged.contains(*o));
+        if uncovered {
+            return Ok(None);
+        }
+
+        let df = PartitionedIpcReader::new(dir.join("catalog"))
+            .with_schema(catalog_schema())
+            .with_org(org)
+            .finish()?;
+
+        let mut sources: HashMap<String, Vec<Term>> = HashMap::new();
+        for (domain_name, source) in df["domain_name"]
+            .utf8()?
+            .into_iter()
+            .zip(df["source"].utf8()?.into_iter())
+        {
+            if let (Some(domain_name), Some(source)) = (domain_name, source) {
+                // `Term::from_str` falls back to `Term::Other`, so it never fails
+                let source = Term::from_str(source)?;
+                let entry = sources.entry(domain_name.to_string()).or_default();
+                if !entry.contains(&source) {
+                    entry.push(source);
+                }
+            }
+        }
+
+        Ok(Some(Self { sources }))
+    }
+
+    /// Sources holding any of `names` or the names matched by `patterns`,
+    /// with a warning for names and patterns which aren't in the database
+    pub fn sources_of(&self, names: &[&str], patterns: &[&NamePattern]) -> Vec<Term> {
+        let mut ret: Vec<Term> = Vec::new();
+        let mut push = |sources: &[Term]| {
+            for source in sources.iter() {
+                if !ret.contains(source) {
+                    ret.push(source.clone());
+                }
+            }
+        };
+
+        for name in names.iter() {
+            match self.sources.get(*name) {
+                Some(sources) => push(sources),
+                None => eprintln!("[WARN] {} is not found in the database", name),
+            }
+        }
+
+        for pattern in patterns.iter() {
+            let mut found = false;
+            for (name, sources) in self.sources.iter() {
+                if pattern.is_match(name) {
+                    push(sources);
+                    found = true;
+                }
+            }
+
+            if !found {
+                eprintln!(
+                    "[WARN] {} matches no name in the database",
+                    pattern.as_str()
+                );
+            }
+        }
+
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test_catalog {
+    use super::*;
+
+    /// Register `names` of `org` into the catalog of `dir`, with an empty domain partition
+    fn write_catalog(dir: &Path, org: &str, names: &[(Term, &str)]) {
+        fs::create_dir_all(dir.join("domain").join(format!("org={}", org))).unwrap();
+
+        let mut records = CatalogRecords::default();
+        for (source, name) in names.iter() {
+            records.push(source, name);
+        }
+        records
+            .write(
+                &dir.join("catalog")
+                    .join(format!("org={}", org))
+                    .join("catalog.ipc"),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sources_of() {
+        let dir = tempfile::tempdir().unwrap();
+        write_catalog(
+            dir.path(),
+            "A",
+            &[
+                (Term::Pfam, "PF00069"),
+                (Term::Pfam, "PF00069"),
+                (Term::ProSiteProfiles, "PS50011"),
+                (Term::Other("NCBIfam".to_string()), "PF00069"),
+                (Term::Gene3D, "G3DSA:1.10.510.10"),
+            ],
+        );
+        write_catalog(dir.path(), "B", &[(Term::SMART, "SM00220")]);
+
+        let catalog = Catalog::read(dir.path(), None).unwrap().unwrap();
+        let mut sources = catalog.sources_of(&["PF00069"], &[]);
+        sources.sort_by_key(|s| s.to_string());
+        assert_eq!(
+            sources,
+            vec![Term::Other("NCBIfam".to_string()), Term::Pfam]
+        );
+
+        // names and patterns which aren't in the database are skipped with a warning
+        let glob = NamePattern::glob("G3DSA:*").unwrap();
+        let (regex, _) = NamePattern::regex("/^PS5/").unwrap();
+        let missing = NamePattern::glob("PTHR*").unwrap();
+        assert_eq!(
+            catalog.sources_of(&["SM00220", "PF99999"], &[&glob, &regex, &missing]),
+            vec![Term::SMART, Term::Gene3D, Term::ProSiteProfiles]
+        );
+        assert!(catalog.sources_of(&["PF99999"], &[]).is_empty());
+
+        // only the catalogs of the selected orgs are read
+        let catalog = Catalog::read(dir.path(), Some(vec!["B".to_string()]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(catalog.sources_of(&["SM00220"], &[]), vec![Term::SMART]);
+        assert!(catalog.sources_of(&["PF00069"], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_read_uncovered() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Catalog::read(dir.path(), None).unwrap().is_some());
+
+        write_catalog(dir.path(), "A", &[(Term::Pfam, "PF00069")]);
+        // registered before catalogs were written
+        fs::create_dir_all(dir.path().join("domain").join("org=B")).unwrap();
+
+        assert!(Catalog::read(dir.path(), None).unwrap().is_none());
+        assert!(Catalog::read(dir.path(), Some(vec!["B".to_string()]))
+            .unwrap()
+            .is_none());
+        assert!(Catalog::read(dir.path(), Some(vec!["A".to_string()]))
+            .unwrap()
+            .is_some());
+    }
+}
@@ -1,41 +1,133 @@
 use anyhow::Result;
 use arrow2::array::ArrayRef;
-use polars_core::POOL;
-use strum::IntoEnumIterator;
 
 use std::{
-    collections::HashMap,
+    collections::{hash_map::Entry, HashMap},
     fs::{self, File},
     io::BufWriter,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use arrow2::{
-    array::{Int16Array, Utf8Array},
+    array::{
+        Float64Array, Int32Array, ListArray, MutableListArray, MutableUtf8Array, TryExtend,
+        Utf8Array,
+    },
     chunk::Chunk,
     datatypes::{DataType, Field, Schema},
     io::ipc::write::{self, Compression, FileWriter},
 };
 
-use super::Term;
+use super::{schema_metadata, CatalogRecords, Term};
 
 pub fn domain_record_schema() -> Schema {
     Schema::from(vec![
-        Field::new("start", DataType::Int16, false),
-        Field::new("end", DataType::Int16, false),
+        Field::new("start", DataType::Int32, false),
+        Field::new("end", DataType::Int32, false),
         Field::new("domain_name", DataType::Utf8, false),
         Field::new("domain_desc", DataType::Utf8, true),
         Field::new("gene_id", DataType::Utf8, false),
+        Field::new("evalue", DataType::Float64, true),
+        Field::new("score", DataType::Float64, true),
+        Field::new("hmm_start", DataType::Int32, true),
+        Field::new("hmm_end", DataType::Int32, true),
+        Field::new("hmm_bounds", DataType::Utf8, true),
+        Field::new("env_start", DataType::Int32, true),
+        Field::new("env_end", DataType::Int32, true),
+        Field::new("entry_type", DataType::Utf8, true),
+        Field::new("entry_name", DataType::Utf8, true),
+        Field::new("status", DataType::Utf8, true),
+        Field::new("date", DataType::Utf8, true),
+        Field::new("match_id", DataType::Utf8, true),
+        Field::new("target_start", DataType::Int32, true),
+        Field::new("target_end", DataType::Int32, true),
+        Field::new("strand", DataType::Utf8, true),
+        Field::new(
+            "attribute_keys",
+            ListArray::<i32>::default_datatype(DataType::Utf8),
+            true,
+        ),
+        Field::new(
+            "attribute_values",
+            ListArray::<i32>::default_datatype(DataType::Utf8),
+            true,
+        ),
     ])
+    .with_metadata(schema_metadata())
+}
+
+/// A single row of the domain table.
+/// Optional fields are only filled by formats which carry them (e.g. JSON).
+#[derive(Debug, Clone, Default)]
+pub struct Domain {
+    pub start: i32,
+    pub end: i32,
+    pub domain_name: String,
+    pub domain_desc: Option<String>,
+    pub gene_id: String,
+    pub evalue: Option<f64>,
+    pub score: Option<f64>,
+    pub hmm_start: Option<i32>,
+    pub hmm_end: Option<i32>,
+    pub hmm_bounds: Option<String>,
+    pub env_start: Option<i32>,
+    pub env_end: Option<i32>,
+    pub entry_type: Option<String>,
+    pub entry_name: Option<String>,
+    pub status: Option<String>,
+    pub date: Option<String>,
+    pub match_id: Option<String>,
+    pub target_start: Option<i32>,
+    pub target_end: Option<i32>,
+    pub strand: Option<String>,
+    /// attributes without a dedicated column as `(key, value)` pairs,
+    /// stored as the parallel `attribute_keys` and `attribute_values` lists
+    pub attributes: Vec<(String, String)>,
+}
+
+impl Domain {
+    pub fn new(
+        start: i32,
+        end: i32,
+        domain_name: String,
+        domain_desc: Option<String>,
+        gene_id: String,
+    ) -> Self {
+        Self {
+            start,
+            end,
+            domain_name,
+            domain_desc,
+            gene_id,
+            ..Default::default()
+        }
+    }
 }
 
 struct DomainRecord {
-    starts: Vec<i16>,
-    ends: Vec<i16>,
+    starts: Vec<i32>,
+    ends: Vec<i32>,
     domain_names: Vec<String>,
     domain_descs: Vec<Option<String>>,
     gene_ids: Vec<String>,
+    evalues: Vec<Option<f64>>,
+    scores: Vec<Option<f64>>,
+    hmm_starts: Vec<Option<i32>>,
+    hmm_ends: Vec<Option<i32>>,
+    hmm_bounds: Vec<Option<String>>,
+    env_starts: Vec<Option<i32>>,
+    env_ends: Vec<Option<i32>>,
+    entry_types: Vec<Option<String>>,
+    entry_names: Vec<Option<String>>,
+    statuses: Vec<Option<String>>,
+    dates: Vec<Option<String>>,
+    match_ids: Vec<Option<String>>,
+    target_starts: Vec<Option<i32>>,
+    target_ends: Vec<Option<i32>>,
+    strands: Vec<Option<String>>,
+    attribute_keys: Vec<Option<Vec<String>>>,
+    attribute_values: Vec<Option<Vec<String>>>,
 }
 
 impl DomainRecord {
@@ -46,22 +138,55 @@ impl DomainRecord {
             domain_names: Vec::new(),
             domain_descs: Vec::new(),
             gene_ids: Vec::new(),
+            evalues: Vec::new(),
+            scores: Vec::new(),
+            hmm_starts: Vec::new(),
+            hmm_ends: Vec::new(),
+            hmm_bounds: Vec::new(),
+            env_starts: Vec::new(),
+            env_ends: Vec::new(),
+            entry_types: Vec::new(),
+            entry_names: Vec::new(),
+            statuses: Vec::new(),
+            dates: Vec::new(),
+            match_ids: Vec::new(),
+            target_starts: Vec::new(),
+            target_ends: Vec::new(),
+            strands: Vec::new(),
+            attribute_keys: Vec::new(),
+            attribute_values: Vec::new(),
         }
     }
 
-    fn push(
-        &mut self,
-        start: i16,
-        end: i16,
-        domain_name: String,
-        domain_desc: Option<String>,
-        gene_id: String,
-    ) {
-        self.starts.push(start);
-        self.ends.push(end);
-        self.domain_names.push(domain_name);
-        self.domain_descs.push(domain_desc);
-        self.gene_ids.push(gene_id);
+    fn push(&mut self, domain: Domain) {
+        self.starts.push(domain.start);
+        self.ends.push(domain.end);
+        self.domain_names.push(domain.domain_name);
+        self.domain_descs.push(domain.domain_desc);
+        self.gene_ids.push(domain.gene_id);
+        self.evalues.push(domain.evalue);
+        self.scores.push(domain.score);
+        self.hmm_starts.push(domain.hmm_start);
+        self.hmm_ends.push(domain.hmm_end);
+        self.hmm_bounds.push(domain.hmm_bounds);
+        self.env_starts.push(domain.env_start);
+        self.env_ends.push(domain.env_end);
+        self.entry_types.push(domain.entry_type);
+        self.entry_names.push(domain.entry_name);
+        self.statuses.push(domain.status);
+        self.dates.push(domain.date);
+        self.match_ids.push(domain.match_id);
+        self.target_starts.push(domain.target_start);
+        self.target_ends.push(domain.target_end);
+        self.strands.push(domain.strand);
+        if domain.attributes.is_empty() {
+            self.attribute_keys.push(None);
+            self.attribute_values.push(None);
+        } else {
+            let (keys, values) = domain.attributes.into_iter().unzip();
+            self.attribute_keys.push(Some(keys));
+            self.attribute_values.push(Some(values));
+        }
     }
 
     pub fn is_empty(&self) -> bool {
@@ -74,108 +199,151 @@ impl DomainRecord {
 
     fn to_chunk(&self) -> Result<Chunk<ArrayRef>> {
         Ok(Chunk::try_new(vec![
-            Arc::new(Int16Array::from_slice(&self.starts)) as ArrayRef,
-            Arc::new(Int16Array::from_slice(&self.ends)) as ArrayRef,
+            Arc::new(Int32Array::from_slice(&self.starts)) as ArrayRef,
+            Arc::new(Int32Array::from_slice(&self.ends)) as ArrayRef,
             Arc::new(Utf8Array::<i32>::from_slice(&self.domain_names)) as ArrayRef,
             Arc::new(Utf8Array::<i32>::from(&self.domain_descs)) as ArrayRef,
             Arc::new(Utf8Array::<i32>::from_slice(&self.gene_ids)) as ArrayRef,
+            Arc::new(Float64Array::from(&self.evalues)) as ArrayRef,
+            Arc::new(Float64Array::from(&self.scores)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.hmm_starts)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.hmm_ends)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.hmm_bounds)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.env_starts)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.env_ends)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.entry_types)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.entry_names)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.statuses)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.dates)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.match_ids)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.target_starts)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.target_ends)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.strands)) as ArrayRef,
+            Arc::new(utf8_list_array(&self.attribute_keys)?) as ArrayRef,
+            Arc::new(utf8_list_array(&self.attribute_values)?) as ArrayRef,
         ])?)
     }
 }
 
+fn utf8_list_array(lists: &[Option<Vec<String>>]) -> Result<ListArray<i32>> {
+    let mut array = MutableListArray::<i32, MutableUtf8Array<i32>>::new();
+    array.try_extend(
+        lists
+            .iter()
+            .map(|list| list.as_ref().map(|list| list.iter().map(Some))),
+    )?;
+
+    Ok(array.into())
+}
+
 impl Default for DomainRecord {
     fn default() -> Self {
         Self::new()
     }
 }
 
+fn create_writer(
+    dir: &Path,
+    source: &Term,
+    schema: &Schema,
+) -> Result<FileWriter<BufWriter<File>>> {
+    let mut path = dir.join(format!("source={}", source));
+    fs::create_dir_all(&path)?;
+
+    path.push(format!("{}.ipc", uuid::Uuid::new_v4()));
+    let file = File::create(path)?;
+    let options = write::WriteOptions {
+        compression: Some(Compression::LZ4),
+    };
+
+    Ok(FileWriter::try_new(
+        BufWriter::new(file),
+        schema,
+        None,
+        options,
+    )?)
+}
+
+/// Domain rows partitioned by source.
+/// Each source is flushed to `dir/source=<source>/<uuid>.ipc` whenever `chunk_size` rows are buffered,
+/// so memory usage doesn't depend on the input size.
 pub struct DomainRecords {
+    dir: PathBuf,
     sources: HashMap<Term, DomainRecord>,
     schema: Schema,
-    chunks: HashMap<Term, Vec<Chunk<ArrayRef>>>,
+    writers: HashMap<Term, FileWriter<BufWriter<File>>>,
     chunk_size: u32,
+    catalog: CatalogRecords,
+    catalog_path: Option<PathBuf>,
 }
 
 impl DomainRecords {
-    pub fn new(chunk_size: u32) -> Self {
+    pub fn new(chunk_size: u32, dir: PathBuf) -> Self {
         Self {
-            sources: Term::iter().map(|t| (t, DomainRecord::default())).collect(),
+            dir,
+            sources: HashMap::new(),
             schema: domain_record_schema(),
-            chunks: Term::iter().map(|t| (t, Vec::new())).collect(),
+            writers: HashMap::new(),
             chunk_size,
+            catalog: CatalogRecords::default(),
+            catalog_path: None,
         }
     }
 
-    fn rechunk(&mut self) -> Result<()> {
-        for (k, v) in self.sources.iter_mut() {
-            if self.chunk_size as usize != v.len() {
-                continue;
-            }
-            let chunk = v.to_chunk()?;
-
-            self.chunks.get_mut(k).unwrap().push(chunk);
-            *v = DomainRecord::default();
-        }
-        Ok(())
+    /// Write the catalog of the pushed domain names to `path` on `finish`
+    pub fn with_catalog(mut self, path: PathBuf) -> Self {
+        self.catalog_path = Some(path);
+        self
     }
 
-    pub fn push(
-        &mut self,
-        source: Term,
-        start: i16,
-        end: i16,
-        domain_name: String,
-        domain_desc: Option<String>,
-        gene_id: String,
-    ) -> Result<()> {
-        if let Some(x) = self.sources.get_mut(&source) {
-            x.push(start, end, domain_name, domain_desc, gene_id)
+    fn flush(&mut self, source: &Term) -> Result<()> {
+        let record = match self.sources.get_mut(source) {
+            Some(record) if !record.is_empty() => record,
+            _ => return Ok(()),
+        };
+
+        let chunk = record.to_chunk()?;
+        *record = DomainRecord::default();
+
+        let writer = match self.writers.entry(source.clone()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(create_writer(&self.dir, source, &self.schema)?),
         };
+        writer.write(&chunk, None)?;
 
-        self.rechunk()?;
         Ok(())
     }
 
-    fn finish(&mut self) -> Result<()> {
-        for (k, v) in self.sources.iter_mut() {
-            if !v.is_empty() {
-                let chunk = v.to_chunk()?;
+    pub fn push(&mut self, source: Term, domain: Domain) -> Result<()> {
+        self.catalog.push(&source, &domain.domain_name);
+
+        // sources are added on demand, since `Term::Other` is open-ended
+        let record = self.sources.entry(source.clone()).or_default();
+        record.push(domain);
+        let len = record.len();
 
-                self.chunks.get_mut(k).unwrap().push(chunk);
-                *v = DomainRecord::default();
-            }
+        if len == self.chunk_size as usize {
+            self.flush(&source)?;
         }
+
         Ok(())
     }
 
-    pub fn write(mut self, dir: PathBuf) -> Result<()> {
-        self.finish()?;
-        println!("------ {} ------", dir.display());
-        POOL.install(|| {
-            self.chunks
-                .into_iter()
-                .map(|(source, batches)| {
-                    let mut path = PathBuf::from(&dir);
-                    path.push(format!("source={}", source));
-
-                    fs::create_dir_all(&path)?;
-
-                    path.push(format!("{}.ipc", uuid::Uuid::new_v4()));
-                    let file = File::create(path)?;
-                    let options = write::WriteOptions {
-                        compression: Some(Compression::LZ4),
-                    };
-                    let mut writer =
-                        FileWriter::try_new(BufWriter::new(file), &self.schema, None, options)?;
-
-                    for chunk in batches.iter() {
-                        writer.write(chunk, None)?;
-                    }
-                    writer.finish()?;
-                    Ok(())
-                })
-                .collect::<Result<Vec<_>>>()
-        })?;
+    /// Flush the remaining rows and close the IPC files
+    pub fn finish(mut self) -> Result<()> {
+        let sources: Vec<Term> = self.sources.keys().cloned().collect();
+        for source in sources.iter() {
+            self.flush(source)?;
+        }
+
+        println!("------ {} ------", self.dir.display());
+        for (_, mut writer) in self.writers.into_iter() {
+            writer.finish()?;
+        }
+
+        if let Some(path) = &self.catalog_path {
+            self.catalog.write(path)?;
+        }
 
         Ok(())
     }
@@ -4,46 +4,83 @@ use arrow2::array::ArrayRef;
 use std::{fs::File, io::BufWriter, path::Path, sync::Arc};
 
 use arrow2::{
-    array::{Int16Array, Utf8Array},
+    array::{Int32Array, Int64Array, Utf8Array},
     chunk::Chunk,
     datatypes::{DataType, Field, Schema},
     io::ipc::write::{self, Compression, FileWriter},
 };
 
+use super::schema_metadata;
+
+/// The organism a protein set is registered as, with optional metadata
+#[derive(Debug, Clone, Default)]
+pub struct Organism {
+    pub name: String,
+    pub taxon_id: Option<i64>,
+    pub strain: Option<String>,
+    pub assembly: Option<String>,
+}
+
 pub struct GeneRecords {
     gene_ids: Vec<String>,
-    seqs: Vec<String>,
-    lengths: Vec<i16>,
+    seqs: Vec<Option<String>>,
+    lengths: Vec<i32>,
+    declared_lengths: Vec<Option<i32>>,
     desc: Vec<Option<String>>,
     organism: Vec<String>,
-    schema: Schema,
+    taxon_ids: Vec<Option<i64>>,
+    strains: Vec<Option<String>>,
+    assemblies: Vec<Option<String>>,
+    md5: Vec<Option<String>>,
     chunk_size: u32,
-    chunks: Vec<Chunk<ArrayRef>>,
+    writer: FileWriter<BufWriter<File>>,
 }
 
 pub fn gene_records_schema() -> Schema {
     Schema::from(vec![
         Field::new("gene_id", DataType::Utf8, false),
-        Field::new("seq", DataType::Utf8, false),
-        Field::new("length", DataType::Int16, false),
+        Field::new("seq", DataType::Utf8, true),
+        Field::new("length", DataType::Int32, false),
+        Field::new("declared_length", DataType::Int32, true),
         Field::new("desc", DataType::Utf8, true),
         Field::new("organism", DataType::Utf8, false),
+        Field::new("taxon_id", DataType::Int64, true),
+        Field::new("strain", DataType::Utf8, true),
+        Field::new("assembly", DataType::Utf8, true),
+        Field::new("md5", DataType::Utf8, true),
     ])
+    .with_metadata(schema_metadata())
+}
+
+/// md5 hex digest of a sequence, the same as the `md5` attribute of InterProScan output
+pub fn seq_md5(seq: &str) -> String {
+    format!("{:x}", md5::compute(seq.as_bytes()))
 }
 
 impl GeneRecords {
-    pub fn new(chunk_size: u32) -> Self {
-        let schema = gene_records_schema();
-        Self {
+    /// Rows are written to the IPC file at `path` whenever `chunk_size` rows are buffered
+    pub fn new(chunk_size: u32, path: &Path) -> Result<Self> {
+        let file = File::create(path)?;
+        let options = write::WriteOptions {
+            compression: Some(Compression::LZ4),
+        };
+        let writer =
+            FileWriter::try_new(BufWriter::new(file), &gene_records_schema(), None, options)?;
+
+        Ok(Self {
             gene_ids: Vec::new(),
             seqs: Vec::new(),
             lengths: Vec::new(),
+            declared_lengths: Vec::new(),
             desc: Vec::new(),
             organism: Vec::new(),
-            schema,
+            taxon_ids: Vec::new(),
+            strains: Vec::new(),
+            assemblies: Vec::new(),
+            md5: Vec::new(),
             chunk_size,
-            chunks: Vec::new(),
-        }
+            writer,
+        })
     }
 
     pub fn push(
@@ -51,17 +88,57 @@ impl GeneRecords {
         gene_id: String,
         seq: String,
         desc: Option<String>,
-        organism: String,
+        organism: &Organism,
     ) -> Result<()> {
         self.rechunk()?;
-        let length = seq.len() as i16;
+        let length = i32::try_from(seq.len())?;
+        self.md5.push(Some(seq_md5(&seq)));
+        self.declared_lengths.push(None);
+        self.push_row(gene_id, Some(seq), length, desc, organism);
+        Ok(())
+    }
+
+    /// Push a protein with the length and md5 declared by the input (e.g. GFF3 `polypeptide` rows).
+    /// Proteins without a sequence keep the declared length as `length`.
+    pub fn push_declared(
+        &mut self,
+        gene_id: String,
+        seq: Option<String>,
+        desc: Option<String>,
+        declared_length: i32,
+        declared_md5: Option<String>,
+        organism: &Organism,
+    ) -> Result<()> {
+        self.rechunk()?;
+        let length = match &seq {
+            Some(seq) => i32::try_from(seq.len())?,
+            None => declared_length,
+        };
+        let md5 = declared_md5.or_else(|| seq.as_deref().map(seq_md5));
+        self.md5.push(md5);
+        self.declared_lengths.push(Some(declared_length));
+        self.push_row(gene_id, seq, length, desc, organism);
+        Ok(())
+    }
+
+    fn push_row(
+        &mut self,
+        gene_id: String,
+        seq: Option<String>,
+        length: i32,
+        desc: Option<String>,
+        organism: &Organism,
+    ) {
         self.gene_ids.push(gene_id);
         self.seqs.push(seq);
         self.lengths.push(length);
         self.desc.push(desc);
-        self.organism.push(organism);
-        Ok(())
+        self.organism.push(organism.name.clone());
+        self.taxon_ids.push(organism.taxon_id);
+        self.strains.push(organism.strain.clone());
+        self.assemblies.push(organism.assembly.clone());
     }
+
     pub fn len(&self) -> usize {
         self.gene_ids.len()
     }
@@ -73,10 +150,15 @@ impl GeneRecords {
     fn to_chunk(&self) -> Result<Chunk<ArrayRef>> {
         Ok(Chunk::try_new(vec![
             Arc::new(Utf8Array::<i32>::from_slice(&self.gene_ids)) as ArrayRef,
-            Arc::new(Utf8Array::<i32>::from_slice(&self.seqs)) as ArrayRef,
-            Arc::new(Int16Array::from_slice(&self.lengths)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.seqs)) as ArrayRef,
+            Arc::new(Int32Array::from_slice(&self.lengths)) as ArrayRef,
+            Arc::new(Int32Array::from(&self.declared_lengths)) as ArrayRef,
             Arc::new(Utf8Array::<i32>::from(&self.desc)) as ArrayRef,
             Arc::new(Utf8Array::<i32>::from_slice(&self.organism)) as ArrayRef,
+            Arc::new(Int64Array::from(&self.taxon_ids)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.strains)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.assemblies)) as ArrayRef,
+            Arc::new(Utf8Array::<i32>::from(&self.md5)) as ArrayRef,
         ])?)
     }
 
@@ -84,17 +166,13 @@ impl GeneRecords {
         self.gene_ids = Vec::new();
         self.seqs = Vec::new();
         self.lengths = Vec::new();
+        self.declared_lengths = Vec::new();
         self.desc = Vec::new();
-        self.organism = Vec::new()
-    }
-
-    fn finish(&mut self) -> Result<()> {
-        if !self.is_empty() {
-            let chunk = self.to_chunk()?;
-            self.chunks.push(chunk);
-            self.init();
-        }
-        Ok(())
+        self.organism = Vec::new();
+        self.taxon_ids = Vec::new();
+        self.strains = Vec::new();
+        self.assemblies = Vec::new();
+        self.md5 = Vec::new();
     }
 
     fn rechunk(&mut self) -> Result<()> {
@@ -103,28 +181,20 @@ impl GeneRecords {
         }
 
         let chunk = self.to_chunk()?;
-
-        self.chunks.push(chunk);
+        self.writer.write(&chunk, None)?;
 
         self.init();
         Ok(())
     }
 
-    pub fn write(mut self, path: &Path) -> Result<()> {
-        self.finish()?;
-        let file = File::create(path)?;
-        let options = write::WriteOptions {
-            compression: Some(Compression::LZ4),
-        };
-
-        let mut writer = FileWriter::try_new(BufWriter::new(file), &self.schema, None, options)?;
-        // writer.start()?;
-
-        for chunk in self.chunks.iter() {
-            writer.write(chunk, None)?;
+    /// Flush the remaining rows and close the IPC file
+    pub fn finish(mut self) -> Result<()> {
+        if !self.is_empty() {
+            let chunk = self.to_chunk()?;
+            self.writer.write(&chunk, None)?;
         }
 
-        writer.finish()?;
+        self.writer.finish()?;
 
         Ok(())
     }
@@ -1,7 +1,96 @@
+mod catalog;
 mod domain;
 mod gene;
+mod registry;
+mod schema;
 mod term;
 
+pub use catalog::*;
 pub use domain::*;
 pub use gene::*;
+pub use registry::*;
+pub use schema::*;
 pub use term::*;
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::fs;
+
+    use anyhow::Result;
+    use polars::prelude::DataFrame;
+
+    use super::{domain_record_schema, gene_records_schema, DomainRecords, GeneRecords};
+    use crate::partition::PartitionedIpcReader;
+
+    /// Register with `f` into a temporary directory and read the domain and gene tables back
+    pub fn register<F>(f: F) -> Result<(DataFrame, DataFrame)>
+    where
+        F: FnOnce(&mut DomainRecords, &mut GeneRecords) -> Result<()>,
+    {
+        let dir = tempfile::tempdir()?;
+        fs::create_dir_all(dir.path().join("gene"))?;
+
+        // small chunks, so flushing in the middle is covered too
+        let mut domain_records = DomainRecords::new(2, dir.path().join("domain"));
+        let mut gene_records = GeneRecords::new(2, &dir.path().join("gene").join("gene.ipc"))?;
+        f(&mut domain_records, &mut gene_records)?;
+        domain_records.finish()?;
+        gene_records.finish()?;
+
+        let domain_df = PartitionedIpcReader::new(dir.path().join("domain"))
+            .with_schema(domain_record_schema())
+            .with_partition_columns(&["source"])
+            .finish()?;
+        let gene_df = PartitionedIpcReader::new(dir.path().join("gene"))
+            .with_schema(gene_records_schema())
+            .finish()?;
+
+        Ok((domain_df, gene_df))
+    }
+
+    fn utf8_values(df: &DataFrame, column: &str) -> Vec<Option<String>> {
+        df[column]
+            .utf8()
+            .unwrap()
+            .into_iter()
+            .map(|v| v.map(|v| v.to_string()))
+            .collect()
+    }
+
+    /// `gene_id source domain_name start end` of each domain row, sorted
+    pub fn domain_rows(df: &DataFrame) -> Vec<String> {
+        let gene_ids = utf8_values(df, "gene_id");
+        let sources = utf8_values(df, "source");
+        let names = utf8_values(df, "domain_name");
+        let starts = df["start"].i32().unwrap();
+        let ends = df["end"].i32().unwrap();
+
+        let mut rows: Vec<String> = (0..df.height())
+            .map(|i| {
+                format!(
+                    "{} {} {} {} {}",
+                    gene_ids[i].as_deref().unwrap_or_default(),
+                    sources[i].as_deref().unwrap_or_default(),
+                    names[i].as_deref().unwrap_or_default(),
+                    starts.get(i).unwrap_or_default(),
+                    ends.get(i).unwrap_or_default()
+                )
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    /// Index of the first row whose `column` is `value`
+    pub fn row_of(df: &DataFrame, column: &str, value: &str) -> usize {
+        utf8_values(df, column)
+            .iter()
+            .position(|v| v.as_deref() == Some(value))
+            .unwrap_or_else(|| panic!("no row with {} = {}", column, value))
+    }
+
+    /// Value of a Utf8 column at `row`
+    pub fn utf8_at(df: &DataFrame, column: &str, row: usize) -> Option<String> {
+        utf8_values(df, column)[row].clone()
+    }
+}
@@ -0,0 +1,242 @@
+use std::{fs::File, io::BufReader, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use super::Term;
+use crate::parser::Expr;
+
+/// Optional override of the built-in rules, placed in the database directory
+pub const TERM_REGISTRY_FILE: &str = "terms.json";
+
+/// Accession prefixes of each source.
+/// A prefix can belong to several sources (e.g. ProSite patterns and profiles share `PS`).
+const BUILTIN_RULES: &[(&str, &[&str])] = &[
+    ("ANF", &["AntiFam"]),
+    ("cd", &["CDD"]),
+    ("Coil", &["Coils"]),
+    ("G3DSA:", &["Gene3D", "FunFam"]),
+    ("GO:", &["GoTerm"]),
+    ("IPR", &["InterPro"]),
+    ("MF_", &["Hamap"]),
+    ("mobidb-lite", &["MobiDBLite"]),
+    ("NF", &["NCBIfam"]),
+    ("PF", &["Pfam"]),
+    ("PIRSF", &["PIRSF"]),
+    ("PIRSR", &["PIRSR"]),
+    ("PR0", &["PRINTS"]),
+    ("PS", &["ProSitePatterns", "ProSiteProfiles"]),
+    ("PTHR", &["PANTHER"]),
+    ("PWY", &["MetaCyc"]),
+    ("R-", &["Reactome"]),
+    ("SFLD", &["SFLD"]),
+    ("SM0", &["SMART"]),
+    ("SSF", &["SUPERFAMILY"]),
+    ("TIGR", &["TIGRFAM", "NCBIfam"]),
+];
+
+/// A rule of `terms.json`, e.g. `{"prefix": "PTHR", "sources": ["PANTHER"]}`
+#[derive(Debug, Deserialize)]
+struct RuleEntry {
+    prefix: String,
+    sources: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    prefix: String,
+    sources: Vec<Term>,
+}
+
+impl Rule {
+    fn new(prefix: &str, sources: &[&str]) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            // `Term::from_str` falls back to `Term::Other`, so it never fails
+            sources: sources
+                .iter()
+                .filter_map(|s| Term::from_str(s).ok())
+                .collect(),
+        }
+    }
+}
+
+/// Maps domain names to the sources they may come from, by the longest matching prefix
+#[derive(Debug, Clone)]
+pub struct TermRegistry {
+    rules: Vec<Rule>,
+}
+
+impl Default for TermRegistry {
+    fn default() -> Self {
+        Self {
+            rules: BUILTIN_RULES
+                .iter()
+                .map(|(prefix, sources)| Rule::new(prefix, sources))
+                .collect(),
+        }
+    }
+}
+
+impl TermRegistry {
+    /// Built-in rules, overridden by `<dir>/terms.json` if it exists.
+    /// A rule in the file replaces the built-in rule of the same prefix.
+    pub fn from_dir(dir: &Path) -> Result<Self> {
+        let mut registry = Self::default();
+
+        let path = dir.join(TERM_REGISTRY_FILE);
+        if !path.exists() {
+            return Ok(registry);
+        }
+
+        let entries: Vec<RuleEntry> =
+            serde_json::from_reader(BufReader::new(File::open(&path)?))
+                .map_err(|e| anyhow!(format!("Invalid {}: {}", path.display(), e)))?;
+
+        for entry in entries.iter() {
+            let sources: Vec<&str> = entry.sources.iter().map(|s| s.as_str()).collect();
+            let rule = Rule::new(&entry.prefix, &sources);
+            match registry.rules.iter_mut().find(|r| r.prefix == rule.prefix) {
+                Some(r) => *r = rule,
+                None => registry.rules.push(rule),
+            }
+        }
+
+        Ok(registry)
+    }
+
+    /// Candidate sources of `name`, `None` if no prefix matches
+    pub fn infer(&self, name: &str) -> Option<&[Term]> {
+        self.rules
+            .iter()
+            .filter(|rule| name.starts_with(&rule.prefix))
+            .max_by_key(|rule| rule.prefix.len())
+            .map(|rule| rule.sources.as_slice())
+    }
+
+    /// Candidate sources of names starting with `prefix` (e.g. the literal prefix of a glob),
+    /// including the sources of longer prefixes
+    fn infer_prefix(&self, prefix: &str) -> Option<Vec<Term>> {
+        let mut sources = self.infer(prefix)?.to_vec();
+        for rule in self.rules.iter() {
+            if rule.prefix.len() > prefix.len() && rule.prefix.starts_with(prefix) {
+                sources.extend(rule.sources.iter().cloned());
+            }
+        }
+        Some(sources)
+    }
+
+    /// Sources of all names and patterns in `expr`.
+    /// `None` if the source of any of them can't be inferred, since every partition may hold it then.
+    pub fn infer_expr(&self, expr: &Expr) -> Option<Vec<Term>> {
+        let mut sources: Vec<Term> = Vec::new();
+
+        let names = expr
+            .names()
+            .into_iter()
+            .map(|name| self.infer(name).map(|s| s.to_vec()));
+        let patterns = expr
+            .patterns()
+            .into_iter()
+            .map(|pattern| self.infer_prefix(pattern.literal_prefix()));
+
+        for candidates in names.chain(patterns) {
+            for source in candidates? {
+                if !sources.contains(&source) {
+                    sources.push(source);
+                }
+            }
+        }
+
+        Some(sources)
+    }
+}
+
+#[cfg(test)]
+mod test_registry {
+    use super::*;
+
+    fn other(name: &str) -> Term {
+        Term::Other(name.to_string())
+    }
+
+    #[test]
+    fn test_infer() {
+        let registry = TermRegistry::default();
+
+        assert_eq!(registry.infer("PF00069"), Some(&[Term::Pfam][..]));
+        assert_eq!(
+            registry.infer("PS50011"),
+            Some(&[Term::ProSitePatterns, Term::ProSiteProfiles][..])
+        );
+        assert_eq!(registry.infer("PIRSR000001-1"), Some(&[Term::PIRSR][..]));
+        assert_eq!(
+            registry.infer("G3DSA:1.10.510.10"),
+            Some(&[Term::Gene3D, other("FunFam")][..])
+        );
+        assert_eq!(registry.infer("GO:0004672"), Some(&[Term::GoTerm][..]));
+        assert_eq!(registry.infer("XYZ00001"), None);
+    }
+
+    #[test]
+    fn test_from_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            TermRegistry::from_dir(dir.path()).unwrap().infer("PS50011"),
+            Some(&[Term::ProSitePatterns, Term::ProSiteProfiles][..])
+        );
+
+        std::fs::write(
+            dir.path().join(TERM_REGISTRY_FILE),
+            r#"[
+                {"prefix": "PS", "sources": ["ProSiteProfiles"]},
+                {"prefix": "PF99", "sources": ["CustomFam"]},
+                {"prefix": "XYZ", "sources": ["Xyz", "Pfam"]}
+            ]"#,
+        )
+        .unwrap();
+        let registry = TermRegistry::from_dir(dir.path()).unwrap();
+
+        // a rule in the file replaces the built-in rule of the same prefix
+        assert_eq!(
+            registry.infer("PS50011"),
+            Some(&[Term::ProSiteProfiles][..])
+        );
+        // the longest prefix wins
+        assert_eq!(registry.infer("PF99001"), Some(&[other("CustomFam")][..]));
+        assert_eq!(registry.infer("PF00069"), Some(&[Term::Pfam][..]));
+        assert_eq!(
+            registry.infer("XYZ00001"),
+            Some(&[other("Xyz"), Term::Pfam][..])
+        );
+
+        std::fs::write(dir.path().join(TERM_REGISTRY_FILE), "{").unwrap();
+        assert!(TermRegistry::from_dir(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_infer_expr() {
+        let registry = TermRegistry::default();
+        let infer = |s: &str| registry.infer_expr(&Expr::from_string(s).unwrap());
+
+        assert_eq!(
+            infer("PF00069 & !PS50011"),
+            Some(vec![
+                Term::Pfam,
+                Term::ProSitePatterns,
+                Term::ProSiteProfiles
+            ])
+        );
+        // the literal prefix of a pattern, including the sources of longer prefixes
+        assert_eq!(infer("/^PF0006[0-9]$/"), Some(vec![Term::Pfam]));
+        assert_eq!(infer("PIRS*"), None);
+        assert_eq!(infer("PIRSF*"), Some(vec![Term::PIRSF]));
+        assert_eq!(
+            infer("PTHR* | G3DSA:*"),
+            Some(vec![Term::PANTHER, Term::Gene3D, other("FunFam")])
+        );
+        // every partition may hold names of unknown prefixes
+        assert_eq!(infer("PF00069 | XYZ00001"), None);
+        assert_eq!(infer("/kinase/"), None);
+    }
+}
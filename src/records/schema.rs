@@ -0,0 +1,12 @@
+use arrow2::datatypes::Metadata;
+
+/// Version of the IPC layout written by `register`, stored in the schema metadata.
+/// Files without it are version 1 (Int16 coordinates).
+pub const SCHEMA_VERSION: u32 = 2;
+pub const SCHEMA_VERSION_KEY: &str = "ipsr.schema_version";
+
+pub fn schema_metadata() -> Metadata {
+    let mut metadata = Metadata::new();
+    metadata.insert(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string());
+    metadata
+}
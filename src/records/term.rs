@@ -1,11 +1,11 @@
-use anyhow::{anyhow, Result};
+use std::{fmt, str::FromStr};
 
-use strum_macros::{Display, EnumIter, EnumString, EnumVariantNames};
-
-use crate::parser::lex::{lex, Token};
+use strum_macros::{EnumIter, EnumString, EnumVariantNames, IntoStaticStr};
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Debug, Clone, PartialEq, Eq, Hash, EnumString, EnumVariantNames, EnumIter, Display)]
+#[derive(
+    Debug, Clone, PartialEq, Eq, Hash, EnumString, EnumVariantNames, EnumIter, IntoStaticStr,
+)]
 pub enum Term {
     #[strum(serialize = ".")]
     ID,
@@ -28,50 +28,73 @@ pub enum Term {
     Reactome,
     MetaCyc,
     InterPro,
+    /// cross references of namespaces without their own source (e.g. KEGG), named `namespace:accession`
+    Xref,
+    /// analyses without a dedicated variant (e.g. NCBIfam, FunFam, Hamap), named as in InterProScan output
+    #[strum(default)]
+    Other(String),
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Term::Other(name) => f.pad(name),
+            term => f.pad(term.into()),
+        }
+    }
 }
 
 impl Term {
-    pub fn try_infer(name: &str) -> Result<Term> {
-        let term = match name {
-            "mobidb-lite" => Term::MobiDBLite,
-            // C
-            n if n.starts_with("cd") => Term::CDD,
-            // G
-            n if n.starts_with("G3DSA") => Term::Gene3D,
-            n if n.starts_with("GO") => Term::GoTerm,
-            // I
-            n if n.starts_with("IRR") => Term::InterPro,
-            // P
-            n if n.starts_with("PTHR") => Term::PANTHER,
-            n if n.starts_with("PWY") => Term::MetaCyc,
-            n if n.starts_with("PS") => Term::ProSiteProfiles,
-            n if n.starts_with("PF") => Term::Pfam,
-            // S
-            n if n.starts_with("SSF") => Term::SUPERFAMILY,
-            n if n.starts_with("SM") => Term::SMART,
-            // T
-            n if n.starts_with("TIGR") => Term::TIGRFAM,
-            // R
-            n if n.starts_with("R-") => Term::Reactome,
+    /// Whether `name` is a built-in source which can qualify names in expressions
+    /// (e.g. `Pfam` of `Pfam:PF00069`). `Term::Other` sources qualify names only when registered.
+    pub fn is_source_name(name: &str) -> bool {
+        !matches!(
+            Term::from_str(name),
+            Ok(Term::ID) | Ok(Term::Other(_)) | Err(_)
+        )
+    }
 
-            _ => return Err(anyhow!(format!("No term is match: {}", name))),
+    /// Map a cross reference (GFF3 `Dbxref`, pathway and GO annotations) to its source and name.
+    /// Unknown namespaces are kept under `Term::Xref` with the namespace as a prefix, so they stay queryable.
+    pub fn from_xref(namespace: &str, accession: &str) -> (Term, String) {
+        let term = match namespace.trim() {
+            "InterPro" => Term::InterPro,
+            "MetaCyc" => Term::MetaCyc,
+            "Reactome" => Term::Reactome,
+            "GO" => return (Term::GoTerm, format!("GO:{}", accession.trim())),
+            namespace => return (Term::Xref, format!("{}:{}", namespace, accession.trim())),
         };
 
-        Ok(term)
-    }
-
-    pub fn try_from_tokens(tokens: &[Token]) -> Vec<Self> {
-        tokens
-            .iter()
-            .filter_map(|token| match token {
-                Token::Name(name) => Term::try_infer(name).ok(),
-                _ => None,
-            })
-            .collect()
+        (term, accession.trim().to_string())
     }
 
-    pub fn try_from_expr(expr: &str) -> Result<Vec<Self>> {
-        let tokens = lex(expr)?;
-        Ok(Self::try_from_tokens(&tokens))
+    /// Convert the library names used in InterProScan JSON/XML output (e.g. `PROSITE_PROFILES`)
+    pub fn from_library(library: &str) -> Term {
+        match library {
+            "CDD" => Term::CDD,
+            "COILS" => Term::Coils,
+            "GENE3D" => Term::Gene3D,
+            "MOBIDB_LITE" | "MOBIDB" => Term::MobiDBLite,
+            "PANTHER" => Term::PANTHER,
+            "PFAM" => Term::Pfam,
+            "PIRSF" => Term::PIRSF,
+            "PIRSR" => Term::PIRSR,
+            "PRINTS" => Term::PRINTS,
+            "PROSITE_PATTERNS" => Term::ProSitePatterns,
+            "PROSITE_PROFILES" => Term::ProSiteProfiles,
+            "SFLD" => Term::SFLD,
+            "SMART" => Term::SMART,
+            "SUPERFAMILY" => Term::SUPERFAMILY,
+            "TIGRFAM" => Term::TIGRFAM,
+            "ANTIFAM" => Term::Other("AntiFam".to_string()),
+            "FUNFAM" => Term::Other("FunFam".to_string()),
+            "HAMAP" => Term::Other("Hamap".to_string()),
+            "NCBIFAM" => Term::Other("NCBIfam".to_string()),
+            "PHOBIUS" => Term::Other("Phobius".to_string()),
+            "SIGNALP_EUK" => Term::Other("SignalP_EUK".to_string()),
+            "SIGNALP_GRAM_NEGATIVE" => Term::Other("SignalP_GRAM_NEGATIVE".to_string()),
+            "SIGNALP_GRAM_POSITIVE" => Term::Other("SignalP_GRAM_POSITIVE".to_string()),
+            library => Term::Other(library.to_string()),
+        }
     }
 }
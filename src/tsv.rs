@@ -0,0 +1,260 @@
+use std::{
+    collections::HashSet,
+    io::BufRead,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    errors::{ErrorHandler, RecordError},
+    fasta::{read_fasta_records, report_unmatched_ids},
+    gff3::read_with_gz,
+    records::{Domain, DomainRecords, GeneRecords, Organism, Term},
+};
+
+// InterProScan TSV columns
+// https://interproscan-docs.readthedocs.io/en/latest/OutputFormats.html#tab-separated-values-format-tsv
+const PROTEIN_ACCESSION: usize = 0;
+const ANALYSIS: usize = 3;
+const SIGNATURE_ACCESSION: usize = 4;
+const SIGNATURE_DESC: usize = 5;
+const START: usize = 6;
+const STOP: usize = 7;
+const SCORE: usize = 8;
+const STATUS: usize = 9;
+const DATE: usize = 10;
+const INTERPRO_ACCESSION: usize = 11;
+const GO_ANNOTATIONS: usize = 13;
+const PATHWAYS: usize = 14;
+
+/// InterProScan writes `-` (or nothing) for missing values
+fn non_empty(value: Option<&str>) -> Option<&str> {
+    match value.map(str::trim) {
+        None | Some("") | Some("-") => None,
+        Some(s) => Some(s),
+    }
+}
+
+pub fn parse_tsvrecord_line(line: &str, domain_records: &mut DomainRecords) -> Result<()> {
+    let line = line.trim_end();
+
+    let records: Vec<&str> = line.split('\t').collect();
+    if records.len() < 11 {
+        return Err(RecordError::InvalidLine {
+            columns: records.len(),
+        }
+        .into());
+    }
+
+    let id = records[PROTEIN_ACCESSION];
+    let source = Term::from_str(records[ANALYSIS])?;
+
+    let start: i32 = records[START].parse()?;
+    let end: i32 = records[STOP].parse()?;
+    // the score column holds the e-value of the match
+    let evalue: Option<f64> = non_empty(records.get(SCORE).copied())
+        .map(|v| v.parse())
+        .transpose()?;
+
+    let status = non_empty(records.get(STATUS).copied()).map(|s| s.to_string());
+    let date = non_empty(records.get(DATE).copied()).map(|s| s.to_string());
+
+    // cross references share the location and the match attributes
+    let row = |domain_name: &str, domain_desc: Option<&str>| Domain {
+        evalue,
+        status: status.clone(),
+        date: date.clone(),
+        ..Domain::new(
+            start,
+            end,
+            domain_name.to_string(),
+            domain_desc.map(|s| s.to_string()),
+            id.to_string(),
+        )
+    };
+
+    let domain_name = non_empty(records.get(SIGNATURE_ACCESSION).copied())
+        .ok_or(RecordError::MissingDomainName)?;
+    let domain_desc = non_empty(records.get(SIGNATURE_DESC).copied());
+
+    if let Some(interpro) = non_empty(records.get(INTERPRO_ACCESSION).copied()) {
+        domain_records.push(Term::InterPro, row(interpro, None))?;
+    }
+
+    if let Some(go_terms) = non_empty(records.get(GO_ANNOTATIONS).copied()) {
+        // GO:0005524|GO:0004672, newer releases append the origin: GO:0005524(InterPro)
+        for term_name in go_terms.split('|') {
+            let term_name = term_name.split('(').next().unwrap_or(term_name).trim();
+            domain_records.push(Term::GoTerm, row(term_name, None))?;
+        }
+    }
+
+    if let Some(pathways) = non_empty(records.get(PATHWAYS).copied()) {
+        // MetaCyc: PWY-5381|Reactome: R-HSA-73614
+        for pathway in pathways.split('|') {
+            let (term, term_name) = match pathway.split_once(':') {
+                Some((type_, term_name)) => Term::from_xref(type_, term_name),
+                None => (Term::Xref, pathway.trim().to_string()),
+            };
+
+            domain_records.push(term, row(&term_name, None))?;
+        }
+    }
+
+    domain_records.push(source, row(domain_name, domain_desc))?;
+
+    Ok(())
+}
+
+pub struct Reader {
+    reader: Box<dyn BufRead>,
+    fasta: Option<PathBuf>,
+    organism: Organism,
+    errors: ErrorHandler,
+}
+
+impl Reader {
+    pub fn from_path<P: AsRef<Path>>(p: &P) -> Result<Self> {
+        Ok(Self {
+            reader: read_with_gz(p)?,
+            fasta: None,
+            organism: Organism::default(),
+            errors: ErrorHandler::default(),
+        })
+    }
+
+    pub fn with_fasta(mut self, fasta: Option<PathBuf>) -> Self {
+        self.fasta = fasta;
+        self
+    }
+
+    pub fn with_organism(mut self, organism: Organism) -> Self {
+        self.organism = organism;
+        self
+    }
+
+    pub fn with_error_handler(mut self, errors: ErrorHandler) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    /// Stream the records into `domain_records` and `gene_records`.
+    /// The gene IDs are kept in memory to report those missing from the FASTA.
+    pub fn finish(
+        mut self,
+        domain_records: &mut DomainRecords,
+        gene_records: &mut GeneRecords,
+    ) -> Result<()> {
+        let fasta = self
+            .fasta
+            .ok_or_else(|| anyhow!("TSV input has no sequences, --fasta is required"))?;
+
+        let mut gene_ids = HashSet::new();
+
+        for (i, line) in self.reader.lines().enumerate() {
+            let line = line?;
+
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+
+            if let Err(e) = parse_tsvrecord_line(&line, domain_records) {
+                self.errors.handle(i + 1, &line, e)?;
+                continue;
+            }
+
+            if let Some(gene_id) = line.split('\t').next() {
+                if !gene_ids.contains(gene_id) {
+                    gene_ids.insert(gene_id.to_string());
+                }
+            }
+        }
+
+        self.errors.finish()?;
+
+        let mut fasta_gene_ids = HashSet::new();
+        for record in read_fasta_records(&fasta)? {
+            let record = record?;
+            fasta_gene_ids.insert(record.gene_id.clone());
+            gene_records.push(record.gene_id, record.seq, record.desc, &self.organism)?;
+        }
+
+        report_unmatched_ids(&gene_ids, &fasta_gene_ids);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_tsv {
+    use super::*;
+    use crate::records::test_utils::{domain_rows, register, row_of, utf8_at};
+
+    const LINE: &str = "P51587\t14086411a2cdf1c4cba63020e1622579\t3418\tPfam\tPF09103\tBRCA2, oligonucleotide/oligosaccharide-binding, domain 1\t2670\t2799\t7.9E-43\tT\t15-03-2013\tIPR015252\tBreast cancer type 2 susceptibility protein, helical domain\tGO:0003677(InterPro)|GO:0006302(PANTHER)\tMetaCyc: PWY-5381|KEGG: 00010+2.7.11.1|Reactome: R-HSA-73614\n";
+
+    #[test]
+    fn test_parse_tsvrecord_line() {
+        let (df, _) =
+            register(|domain_records, _| parse_tsvrecord_line(LINE, domain_records)).unwrap();
+
+        assert_eq!(
+            domain_rows(&df),
+            vec![
+                "P51587 GoTerm GO:0003677 2670 2799",
+                "P51587 GoTerm GO:0006302 2670 2799",
+                "P51587 InterPro IPR015252 2670 2799",
+                "P51587 MetaCyc PWY-5381 2670 2799",
+                "P51587 Pfam PF09103 2670 2799",
+                "P51587 Reactome R-HSA-73614 2670 2799",
+                "P51587 Xref KEGG:00010+2.7.11.1 2670 2799",
+            ]
+        );
+
+        let i = row_of(&df, "domain_name", "PF09103");
+        assert_eq!(
+            utf8_at(&df, "domain_desc", i).as_deref(),
+            Some("BRCA2, oligonucleotide/oligosaccharide-binding, domain 1")
+        );
+        assert_eq!(df["evalue"].f64().unwrap().get(i), Some(7.9E-43));
+        assert_eq!(utf8_at(&df, "status", i).as_deref(), Some("T"));
+        assert_eq!(utf8_at(&df, "date", i).as_deref(), Some("15-03-2013"));
+
+        // cross references don't take the description of the signature
+        let i = row_of(&df, "domain_name", "IPR015252");
+        assert_eq!(utf8_at(&df, "domain_desc", i), None);
+        assert_eq!(df["evalue"].f64().unwrap().get(i), Some(7.9E-43));
+    }
+
+    #[test]
+    fn test_parse_tsvrecord_line_optional_columns() {
+        // older releases write only 11 columns and `-` for a missing score
+        let line = "g1\tmd5\t100\tNCBIfam\tNF000001\t-\t1\t50\t-\tT\t01-01-2022";
+        let (df, _) =
+            register(|domain_records, _| parse_tsvrecord_line(line, domain_records)).unwrap();
+
+        assert_eq!(domain_rows(&df), vec!["g1 NCBIfam NF000001 1 50"]);
+        assert_eq!(utf8_at(&df, "domain_desc", 0), None);
+        assert_eq!(df["evalue"].f64().unwrap().get(0), None);
+    }
+
+    #[test]
+    fn test_parse_tsvrecord_line_errors() {
+        let kind = |line: &str| {
+            register(|domain_records, _| parse_tsvrecord_line(line, domain_records))
+                .unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(kind("g1\tmd5\t100\tPfam"), "Invalid line with 4 columns");
+        assert_eq!(
+            kind("g1\tmd5\t100\tPfam\t-\t-\t1\t50\t-\tT\t01-01-2022"),
+            "domain name is required"
+        );
+        assert_eq!(
+            kind("g1\tmd5\t100\tPfam\tPF00001\t-\tx\t50\t-\tT\t01-01-2022"),
+            "invalid digit found in string"
+        );
+    }
+}
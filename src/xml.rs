@@ -0,0 +1,300 @@
+use std::{io::Read, path::Path, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use roxmltree::{Document, Node};
+
+use crate::{
+    gff3::read_with_gz,
+    json::{push_match, Entry, GoXref, LibraryRelease, Location, Match, PathwayXref, Signature},
+    records::{DomainRecords, GeneRecords, Organism},
+};
+
+// https://interproscan-docs.readthedocs.io/en/latest/OutputFormats.html#xml-output
+// Match and location elements are named after the search method (e.g. hmmer3-match, hmmer3-location),
+// so they are handled by position instead of by name.
+
+fn child<'a, 'input>(node: &Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(
+    node: &Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn required_attr<'a>(node: &Node<'a, '_>, name: &str) -> Result<&'a str> {
+    node.attribute(name).ok_or_else(|| {
+        anyhow!(format!(
+            "<{}> has no {} attribute",
+            node.tag_name().name(),
+            name
+        ))
+    })
+}
+
+fn parse_attr<T: FromStr>(node: &Node, name: &str) -> Result<Option<T>> {
+    match node.attribute(name) {
+        Some(v) => v
+            .parse()
+            .map(Some)
+            .map_err(|_| anyhow!(format!("Invalid {}: {}", name, v))),
+        None => Ok(None),
+    }
+}
+
+fn parse_entry(node: &Node) -> Result<Entry> {
+    Ok(Entry {
+        accession: required_attr(node, "ac")?.to_string(),
+        name: node.attribute("name").map(|s| s.to_string()),
+        type_: node.attribute("type").map(|s| s.to_string()),
+        go_xrefs: children(node, "go-xref")
+            .map(|n| {
+                Ok(GoXref {
+                    id: required_attr(&n, "id")?.to_string(),
+                })
+            })
+            .collect::<Result<_>>()?,
+        pathway_xrefs: children(node, "pathway-xref")
+            .map(|n| {
+                Ok(PathwayXref {
+                    database_name: required_attr(&n, "db")?.to_string(),
+                    id: required_attr(&n, "id")?.to_string(),
+                })
+            })
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn parse_signature(node: &Node) -> Result<Signature> {
+    let library = child(node, "signature-library-release")
+        .ok_or_else(|| anyhow!("<signature> has no <signature-library-release>"))?;
+
+    Ok(Signature {
+        accession: required_attr(node, "ac")?.to_string(),
+        name: node.attribute("name").map(|s| s.to_string()),
+        description: node.attribute("desc").map(|s| s.to_string()),
+        library_release: LibraryRelease {
+            library: required_attr(&library, "library")?.to_string(),
+        },
+        entry: child(node, "entry").map(|n| parse_entry(&n)).transpose()?,
+    })
+}
+
+fn parse_location(node: &Node) -> Result<Location> {
+    Ok(Location {
+        start: parse_attr(node, "start")?.ok_or_else(|| anyhow!("location start is required"))?,
+        end: parse_attr(node, "end")?.ok_or_else(|| anyhow!("location end is required"))?,
+        evalue: parse_attr(node, "evalue")?,
+        score: parse_attr(node, "score")?,
+        hmm_start: parse_attr(node, "hmm-start")?,
+        hmm_end: parse_attr(node, "hmm-end")?,
+        hmm_bounds: node.attribute("hmm-bounds").map(|s| s.to_string()),
+        env_start: parse_attr(node, "env-start")?,
+        env_end: parse_attr(node, "env-end")?,
+    })
+}
+
+fn parse_match(node: &Node) -> Result<Match> {
+    let signature = child(node, "signature")
+        .ok_or_else(|| anyhow!(format!("<{}> has no <signature>", node.tag_name().name())))?;
+
+    let locations = match child(node, "locations") {
+        Some(locations) => locations
+            .children()
+            .filter(|n| n.is_element())
+            .map(|n| parse_location(&n))
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+
+    Ok(Match {
+        signature: parse_signature(&signature)?,
+        locations,
+    })
+}
+
+pub struct Reader {
+    text: String,
+    organism: Organism,
+}
+
+impl Reader {
+    pub fn from_path<P: AsRef<Path>>(p: &P) -> Result<Self> {
+        let mut text = String::new();
+        read_with_gz(p)?.read_to_string(&mut text)?;
+
+        Ok(Self {
+            text,
+            organism: Organism::default(),
+        })
+    }
+
+    pub fn with_organism(mut self, organism: Organism) -> Self {
+        self.organism = organism;
+        self
+    }
+
+    pub fn finish(
+        self,
+        domain_records: &mut DomainRecords,
+        gene_records: &mut GeneRecords,
+    ) -> Result<()> {
+        let doc = Document::parse(&self.text)?;
+
+        for protein in doc
+            .descendants()
+            .filter(|n| n.is_element() && n.tag_name().name() == "protein")
+        {
+            let seq: String = child(&protein, "sequence")
+                .and_then(|n| n.text())
+                .ok_or_else(|| anyhow!("<protein> has no <sequence>"))?
+                .split_whitespace()
+                .collect();
+
+            let matches = match child(&protein, "matches") {
+                Some(matches) => matches
+                    .children()
+                    .filter(|n| n.is_element())
+                    .map(|n| parse_match(&n))
+                    .collect::<Result<Vec<_>>>()?,
+                None => Vec::new(),
+            };
+
+            // identical sequences are merged into a single protein with several xrefs
+            for xref in children(&protein, "xref") {
+                let gene_id = required_attr(&xref, "id")?;
+
+                for m in matches.iter() {
+                    push_match(m, gene_id, domain_records)?;
+                }
+
+                let desc = xref
+                    .attribute("desc")
+                    .or_else(|| {
+                        xref.attribute("name")
+                            .and_then(|name| name.strip_prefix(gene_id))
+                    })
+                    .map(|desc| desc.trim().to_string())
+                    .filter(|desc| !desc.is_empty());
+
+                gene_records.push(gene_id.to_string(), seq.clone(), desc, &self.organism)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_xml {
+    use super::*;
+    use crate::records::test_utils::{domain_rows, register, row_of, utf8_at};
+
+    // two identical sequences merged into a single protein with two xrefs
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<protein-matches xmlns="https://ftp.ebi.ac.uk/pub/software/unix/iprscan/5/schemas" interproscan-version="5.59-91.0">
+  <protein>
+    <sequence md5="0c5d6e4c4fb2d0e6c2a8a1f4a3d6f7e1">MKVLA
+AGIV</sequence>
+    <xref id="g1" name="g1 serine/threonine kinase"/>
+    <xref id="g2" desc="kinase-like protein" name="g2 kinase-like protein"/>
+    <matches>
+      <hmmer3-match evalue="1.5E-20" score="70.1">
+        <signature ac="PF00069" desc="Protein kinase domain" name="Pkinase">
+          <entry ac="IPR000719" desc="Protein kinase domain" name="PROTEIN_KINASE_DOM" type="DOMAIN">
+            <go-xref category="MOLECULAR_FUNCTION" db="GO" id="GO:0004672" name="protein kinase activity"/>
+            <pathway-xref db="Reactome" id="R-HSA-162582" name="Signaling"/>
+          </entry>
+          <models>
+            <model ac="PF00069" desc="Protein kinase domain" name="Pkinase"/>
+          </models>
+          <signature-library-release library="PFAM" version="35.0"/>
+        </signature>
+        <locations>
+          <hmmer3-location env-end="9" env-start="1" score="70.1" evalue="1.5E-20" hmm-start="3" hmm-end="250" hmm-length="264" hmm-bounds="INCOMPLETE" start="2" end="8" representative="false"/>
+        </locations>
+      </hmmer3-match>
+      <hmmer3-match evalue="1.0E-5" score="20.0">
+        <signature ac="NF000001">
+          <signature-library-release library="NCBIFAM" version="9.0"/>
+        </signature>
+        <locations>
+          <hmmer3-location start="1" end="4"/>
+          <hmmer3-location start="6" end="9"/>
+        </locations>
+      </hmmer3-match>
+    </matches>
+  </protein>
+</protein-matches>
+"#;
+
+    #[test]
+    fn test_reader() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.xml");
+        std::fs::write(&path, XML).unwrap();
+
+        let (domain_df, gene_df) = register(|domain_records, gene_records| {
+            Reader::from_path(&path)?.finish(domain_records, gene_records)
+        })
+        .unwrap();
+
+        let mut expected = Vec::new();
+        for gene_id in ["g1", "g2"] {
+            expected.push(format!("{} GoTerm GO:0004672 2 8", gene_id));
+            expected.push(format!("{} InterPro IPR000719 2 8", gene_id));
+            expected.push(format!("{} NCBIfam NF000001 1 4", gene_id));
+            expected.push(format!("{} NCBIfam NF000001 6 9", gene_id));
+            expected.push(format!("{} Pfam PF00069 2 8", gene_id));
+            expected.push(format!("{} Reactome R-HSA-162582 2 8", gene_id));
+        }
+        assert_eq!(domain_rows(&domain_df), expected);
+
+        let i = row_of(&domain_df, "domain_name", "PF00069");
+        assert_eq!(
+            utf8_at(&domain_df, "domain_desc", i).as_deref(),
+            Some("Protein kinase domain")
+        );
+        assert_eq!(domain_df["evalue"].f64().unwrap().get(i), Some(1.5E-20));
+        assert_eq!(domain_df["hmm_start"].i32().unwrap().get(i), Some(3));
+        assert_eq!(domain_df["env_end"].i32().unwrap().get(i), Some(9));
+        assert_eq!(
+            utf8_at(&domain_df, "hmm_bounds", i).as_deref(),
+            Some("INCOMPLETE")
+        );
+        assert_eq!(
+            utf8_at(&domain_df, "entry_type", i).as_deref(),
+            Some("DOMAIN")
+        );
+
+        assert_eq!(gene_df.height(), 2);
+        let i = row_of(&gene_df, "gene_id", "g1");
+        assert_eq!(
+            utf8_at(&gene_df, "desc", i).as_deref(),
+            Some("serine/threonine kinase")
+        );
+        // whitespaces in <sequence> are removed
+        assert_eq!(utf8_at(&gene_df, "seq", i).as_deref(), Some("MKVLAAGIV"));
+        let i = row_of(&gene_df, "gene_id", "g2");
+        assert_eq!(
+            utf8_at(&gene_df, "desc", i).as_deref(),
+            Some("kinase-like protein")
+        );
+    }
+
+    #[test]
+    fn test_parse_match_errors() {
+        let doc = Document::parse(r#"<hmmer3-match><locations/></hmmer3-match>"#).unwrap();
+        assert!(parse_match(&doc.root_element()).is_err());
+
+        let doc = Document::parse(
+            r#"<hmmer3-match><signature ac="PF00069"><signature-library-release library="PFAM"/></signature><locations><hmmer3-location start="x" end="4"/></locations></hmmer3-match>"#,
+        )
+        .unwrap();
+        assert!(parse_match(&doc.root_element()).is_err());
+    }
+}